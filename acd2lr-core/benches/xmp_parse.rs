@@ -0,0 +1,74 @@
+use std::convert::TryFrom;
+
+use acd2lr_core::{xmp::XmpData, xpacket::XPacket};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+/// Body of the XPacket in `bytes`
+fn body(bytes: &[u8]) -> Vec<u8> {
+    XPacket::try_from(bytes)
+        .expect("failed to parse xpacket")
+        .body
+        .to_vec()
+}
+
+/// The Lightroom fixture, with its `xmpMM:History` entry repeated `count` times
+fn with_history(count: usize) -> Vec<u8> {
+    let body = String::from_utf8(body(include_bytes!("../tests/data/lightroom_data.xpacket")))
+        .expect("invalid utf-8 in fixture");
+
+    let history = body.find("<xmpMM:History>").expect("missing history");
+    let start = history
+        + body[history..]
+            .find("<rdf:li")
+            .expect("missing history entry");
+    let end = start
+        + body[start..]
+            .find("/>")
+            .expect("unterminated history entry")
+        + 2;
+
+    let mut result = String::with_capacity(body.len() + (end - start) * count);
+    result.push_str(&body[..start]);
+    for _ in 0..count {
+        result.push_str(&body[start..end]);
+    }
+    result.push_str(&body[end..]);
+
+    result.into_bytes()
+}
+
+fn bench_xmp_parse(c: &mut Criterion) {
+    let inputs = vec![
+        (
+            "acdsee_data",
+            body(include_bytes!("../tests/data/acdsee_data.xpacket")),
+        ),
+        (
+            "lightroom_data",
+            body(include_bytes!("../tests/data/lightroom_data.xpacket")),
+        ),
+        ("lightroom_data_history_x10", with_history(10)),
+    ];
+
+    let mut group = c.benchmark_group("xmp_parse");
+    for (name, input) in &inputs {
+        group.throughput(Throughput::Bytes(input.len() as u64));
+        group.bench_with_input(BenchmarkId::new("parse", name), input, |b, input| {
+            b.iter(|| XmpData::parse(input).expect("failed to parse xmp"))
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("acdsee_data");
+    for (name, input) in &inputs {
+        let xmp = XmpData::parse(input).expect("failed to parse xmp");
+
+        group.bench_with_input(BenchmarkId::from_parameter(name), &xmp, |b, xmp| {
+            b.iter(|| xmp.acdsee_data())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_xmp_parse);
+criterion_main!(benches);
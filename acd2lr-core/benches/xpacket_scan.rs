@@ -0,0 +1,55 @@
+use std::path::{Path, PathBuf};
+
+use acd2lr_core::file::XPacketFile;
+use async_std::{fs::File, task::block_on};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+const XPACKET: &[u8] = include_bytes!("../tests/data/acdsee_data.xpacket");
+
+/// Write a file of `size` bytes ending with an XPacket, which is the worst case for the scan
+fn synthetic_file(size: usize) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("acd2lr_bench_xpacket_scan_{}.bin", size));
+
+    if std::fs::metadata(&path).ok().map(|m| m.len() as usize) != Some(size) {
+        // Binary-looking filler, with the occasional '<' for the scan to reject
+        let mut bytes: Vec<u8> = (0..size - XPACKET.len())
+            .map(|i| (i * 31 % 251) as u8)
+            .collect();
+        bytes.extend_from_slice(XPACKET);
+
+        std::fs::write(&path, bytes).expect("failed to write benchmark file");
+    }
+
+    path
+}
+
+async fn scan(path: &Path) {
+    let file = File::open(path).await.expect("failed to open file");
+    let xpacket = XPacketFile::open(file)
+        .await
+        .map_err(|(error, _)| error)
+        .expect("failed to scan file");
+
+    assert!(xpacket.span().is_some());
+}
+
+fn bench_xpacket_scan(c: &mut Criterion) {
+    let mut group = c.benchmark_group("xpacket_scan");
+    group.sample_size(10);
+
+    for &size in &[1 << 20, 10 << 20, 100 << 20] {
+        let path = synthetic_file(size);
+
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{} MB", size >> 20)),
+            &path,
+            |b, path| b.iter(|| block_on(scan(path))),
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_xpacket_scan);
+criterion_main!(benches);
@@ -0,0 +1,74 @@
+use std::path::{Path, PathBuf};
+
+use acd2lr_core::file::XPacketFile;
+use async_std::{fs::OpenOptions, task::block_on};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+const XPACKET: &[u8] = include_bytes!("../tests/data/acdsee_data.xpacket");
+
+/// Write a file holding an XPacket padded to `size` bytes
+fn synthetic_file(size: usize) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("acd2lr_bench_xpacket_write_{}.bin", size));
+
+    // Insert the padding before the trailer of the packet
+    let trailer = XPACKET
+        .windows(13)
+        .rposition(|w| w == b"<?xpacket end")
+        .expect("missing xpacket trailer");
+
+    let mut bytes = vec![0u8; 4096];
+    bytes.extend_from_slice(&XPACKET[..trailer]);
+    bytes.resize(4096 + size - (XPACKET.len() - trailer), b' ');
+    bytes.extend_from_slice(&XPACKET[trailer..]);
+
+    std::fs::write(&path, bytes).expect("failed to write benchmark file");
+
+    path
+}
+
+async fn open(path: &Path) -> (XPacketFile, Vec<u8>) {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .await
+        .expect("failed to open file");
+    let mut xpacket = XPacketFile::open(file)
+        .await
+        .map_err(|(error, _)| error)
+        .expect("failed to scan file");
+
+    let packet = xpacket
+        .read_packet_bytes()
+        .await
+        .expect("failed to read packet")
+        .expect("missing packet");
+
+    (xpacket, packet)
+}
+
+fn bench_xpacket_write(c: &mut Criterion) {
+    let mut group = c.benchmark_group("xpacket_write");
+
+    for &size in &[16 << 10, 128 << 10, 1 << 20] {
+        let path = synthetic_file(size);
+        let (mut xpacket, packet) = block_on(open(&path));
+        assert_eq!(packet.len(), size);
+
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{} KB", size >> 10)),
+            &packet,
+            |b, packet| {
+                b.iter(|| {
+                    block_on(xpacket.write_packet_bytes(packet)).expect("failed to write packet")
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_xpacket_write);
+criterion_main!(benches);
@@ -1,34 +1,46 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use crate::{
+    ns,
     xmp::{rules, RewriteRule},
     TagHierarchy,
 };
 
-#[derive(Default, Debug, Clone, Serialize)]
+/// Metadata stored by ACDSee in the `acdsee:` XMP namespace
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AcdSeeData {
+    /// Caption of the picture
     #[serde(skip_serializing_if = "Option::is_none")]
     pub caption: Option<String>,
+    /// Date and time the picture was taken
     #[serde(skip_serializing_if = "Option::is_none")]
     pub datetime: Option<chrono::NaiveDateTime>,
+    /// Author of the picture
     #[serde(skip_serializing_if = "Option::is_none")]
     pub author: Option<String>,
+    /// Rating, from 1 to 5
     #[serde(skip_serializing_if = "Option::is_none")]
     pub rating: Option<i32>,
+    /// Free-form notes
     #[serde(skip_serializing_if = "Option::is_none")]
     pub notes: Option<String>,
+    /// Whether the picture was tagged in ACDSee
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tagged: Option<bool>,
+    /// Assigned categories
     #[serde(skip_serializing_if = "Option::is_none")]
     pub categories: Option<TagHierarchy>,
+    /// Collections the picture belongs to, as stored by ACDSee
     #[serde(skip_serializing_if = "Option::is_none")]
     pub collections: Option<String>,
-    #[serde(skip_serializing_if = "Vec::is_empty")]
+    /// Keywords, in order of first occurrence
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub keywords: Vec<String>,
 }
 
 impl AcdSeeData {
+    /// Returns true if none of the fields is set
     pub fn is_empty(&self) -> bool {
         self.caption.is_none()
             && self.datetime.is_none()
@@ -40,37 +52,343 @@ impl AcdSeeData {
             && self.collections.is_none()
     }
 
+    /// Number of keywords
+    pub fn keyword_count(&self) -> usize {
+        self.keywords.len()
+    }
+
+    /// Number of assigned categories, or 0 if there are none
+    pub fn category_count(&self) -> usize {
+        self.categories.as_ref().map(|h| h.len()).unwrap_or(0)
+    }
+
+    /// Rules converting this data to the standard XMP fields
     pub fn to_ruleset(&self) -> Vec<RewriteRule> {
+        self.to_ruleset_with_mapping(&FieldMapping::default())
+    }
+
+    /// Rules converting this data, writing the text fields to the targets of `mapping`
+    pub fn to_ruleset_with_mapping(&self, mapping: &FieldMapping) -> Vec<RewriteRule> {
         let mut result = Vec::with_capacity(8);
 
         if let Some(caption) = &self.caption {
-            result.push(rules::set_dc_title(caption.clone()));
+            result.extend(mapping.caption_to.iter().map(|t| t.rule(caption.clone())));
         }
 
         if let Some(author) = &self.author {
-            result.push(rules::set_dc_creator(author.clone()));
+            result.extend(mapping.author_to.iter().map(|t| t.rule(author.clone())));
         }
 
         if let Some(notes) = &self.notes {
-            result.push(rules::set_dc_description(notes.clone()));
+            result.extend(mapping.notes_to.iter().map(|t| t.rule(notes.clone())));
         }
 
         if let Some(categories) = &self.categories {
-            result.push(rules::set_lr_hierarchical_subject(categories));
+            result.push(rules::set_lr_hierarchical_subject_with_separator(
+                categories,
+                mapping.tag_separator,
+            ));
         }
 
         if !self.keywords.is_empty() {
             result.push(rules::set_dc_subject(self.keywords.clone()));
         }
 
+        // Only strip the history of files which are converted
+        if mapping.strip_history_before_convert && !result.is_empty() {
+            result.push(rules::strip_xmp_mm_history());
+        }
+
+        if mapping.strip_acdsee_after_convert && !result.is_empty() {
+            result.extend(rules::strip_acdsee_fields());
+        }
+
         result
     }
+
+    /// Rules writing this data back to the ACDSee fields
+    pub fn to_acdsee_ruleset(&self) -> Vec<RewriteRule> {
+        let mut result = Vec::with_capacity(9);
+
+        if let Some(caption) = &self.caption {
+            result.push(rules::set_acdsee_value("caption", caption.clone()));
+        }
+
+        if let Some(datetime) = &self.datetime {
+            result.push(rules::set_acdsee_value(
+                "datetime",
+                datetime.format("%Y-%m-%dT%H:%M:%S%.3f").to_string(),
+            ));
+        }
+
+        if let Some(author) = &self.author {
+            result.push(rules::set_acdsee_value("author", author.clone()));
+        }
+
+        if let Some(rating) = &self.rating {
+            result.push(rules::set_acdsee_value("rating", rating.to_string()));
+        }
+
+        if let Some(notes) = &self.notes {
+            result.push(rules::set_acdsee_value("notes", notes.clone()));
+        }
+
+        if let Some(tagged) = &self.tagged {
+            result.push(rules::set_acdsee_value(
+                "tagged",
+                if *tagged { "True" } else { "False" }.to_owned(),
+            ));
+        }
+
+        if let Some(categories) = &self.categories {
+            result.push(rules::set_acdsee_value(
+                "categories",
+                categories.to_acdsee_categories(),
+            ));
+        }
+
+        if let Some(collections) = &self.collections {
+            result.push(rules::set_acdsee_value("collections", collections.clone()));
+        }
+
+        if !self.keywords.is_empty() {
+            result.push(rules::set_acdsee_keywords(self.keywords.clone()));
+        }
+
+        result
+    }
+}
+
+/// An XMP property ACDSee text fields can be written to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct XmpTarget {
+    /// Namespace of the property
+    pub namespace: &'static str,
+    /// Conventional prefix of the namespace
+    pub prefix: &'static str,
+    /// Local name of the property
+    pub local_name: &'static str,
+}
+
+impl XmpTarget {
+    /// `dc:title`
+    pub const DC_TITLE: Self = Self::new(ns::DC, "dc", "title");
+    /// `dc:description`
+    pub const DC_DESCRIPTION: Self = Self::new(ns::DC, "dc", "description");
+    /// `dc:creator`
+    pub const DC_CREATOR: Self = Self::new(ns::DC, "dc", "creator");
+    /// `xmp:Description`
+    pub const XMP_DESCRIPTION: Self = Self::new(ns::XMP, "xmp", "Description");
+    /// `xmp:Label`
+    pub const XMP_LABEL: Self = Self::new(ns::XMP, "xmp", "Label");
+
+    /// Targets which can be selected in a [`FieldMapping`]
+    pub const KNOWN: &'static [Self] = &[
+        Self::DC_TITLE,
+        Self::DC_DESCRIPTION,
+        Self::DC_CREATOR,
+        Self::XMP_DESCRIPTION,
+        Self::XMP_LABEL,
+    ];
+
+    const fn new(namespace: &'static str, prefix: &'static str, local_name: &'static str) -> Self {
+        Self {
+            namespace,
+            prefix,
+            local_name,
+        }
+    }
+
+    /// Rule writing `value` to this target, using the value type the property expects
+    fn rule(&self, value: String) -> RewriteRule {
+        match *self {
+            Self::DC_TITLE | Self::DC_DESCRIPTION => rules::set_rdf_alt_with_lang(
+                self.namespace,
+                self.prefix,
+                self.local_name,
+                vec![("x-default".to_string(), value)],
+            ),
+            Self::DC_CREATOR => {
+                rules::set_rdf_seq(self.namespace, self.prefix, self.local_name, vec![value])
+            }
+            _ => rules::set_simple_value(self.namespace, self.prefix, self.local_name, value),
+        }
+    }
+}
+
+impl std::fmt::Display for XmpTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.prefix, self.local_name)
+    }
+}
+
+/// Error for a target name which is not one of [`XmpTarget::KNOWN`]
+#[derive(Debug, Error)]
+#[error("unknown XMP target: {0}")]
+pub struct UnknownXmpTarget(pub String);
+
+impl std::str::FromStr for XmpTarget {
+    type Err = UnknownXmpTarget;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::KNOWN
+            .iter()
+            .find(|target| target.to_string() == s)
+            .copied()
+            .ok_or_else(|| UnknownXmpTarget(s.to_owned()))
+    }
+}
+
+impl std::convert::TryFrom<String> for XmpTarget {
+    type Error = UnknownXmpTarget;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<XmpTarget> for String {
+    fn from(target: XmpTarget) -> Self {
+        target.to_string()
+    }
 }
 
+/// XMP properties the ACDSee text fields are converted to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FieldMapping {
+    /// Targets of [`AcdSeeData::caption`]
+    pub caption_to: Vec<XmpTarget>,
+    /// Targets of [`AcdSeeData::notes`]
+    pub notes_to: Vec<XmpTarget>,
+    /// Targets of [`AcdSeeData::author`]
+    pub author_to: Vec<XmpTarget>,
+    /// Separator between the levels of the tags written to `lr:hierarchicalSubject`
+    pub tag_separator: char,
+    /// Remove `xmpMM:History` when converting, to make room in the XPacket
+    pub strip_history_before_convert: bool,
+    /// Remove the ACDSee fields once they are converted
+    pub strip_acdsee_after_convert: bool,
+}
+
+impl Default for FieldMapping {
+    fn default() -> Self {
+        Self {
+            caption_to: vec![XmpTarget::DC_TITLE],
+            notes_to: vec![XmpTarget::DC_DESCRIPTION],
+            author_to: vec![XmpTarget::DC_CREATOR],
+            tag_separator: '|',
+            strip_history_before_convert: false,
+            strip_acdsee_after_convert: false,
+        }
+    }
+}
+
+/// Error decoding an ACDSee field
 #[derive(Debug, Error)]
 pub enum AcdSeeError {
+    /// The categories are not valid XML
     #[error(transparent)]
     Xml(#[from] xml::reader::Error),
+    /// The date is not in the expected format
     #[error(transparent)]
     Date(#[from] chrono::ParseError),
 }
+
+/// Fields which could not be decoded when reading ACDSee data
+#[derive(Debug, Clone, Default)]
+pub struct AcdSeeWarning {
+    /// Field name and error message for each invalid field
+    pub fields: Vec<(String, String)>,
+}
+
+impl AcdSeeWarning {
+    /// Returns true if all the fields were valid
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Returns the decoded value of a field, recording the error if it is invalid
+    pub(crate) fn check<T>(
+        &mut self,
+        field: &str,
+        result: Result<Option<T>, AcdSeeError>,
+    ) -> Option<T> {
+        match result {
+            Ok(value) => value,
+            Err(error) => {
+                self.fields.push((field.to_owned(), error.to_string()));
+                None
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for AcdSeeWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, (field, message)) in self.fields.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+
+            write!(f, "{}: {}", field, message)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A value that can be decoded from the text of an ACDSee field
+///
+/// Field values are trimmed before being decoded, and empty fields are treated as missing by
+/// [`XmpData::acdsee_data`](crate::xmp::XmpData::acdsee_data), so implementations are only called
+/// on non-empty values. Lenient types such as `i32` and `bool` fall back to a default value
+/// instead of failing, while dates and categories return an [`AcdSeeError`] when they cannot be
+/// parsed.
+pub trait FromAcdSee: Sized {
+    /// Decode `value`
+    ///
+    /// The `Option<T>` implementation returns `Ok(None)` for an empty value.
+    fn from_acdsee(value: &str) -> Result<Self, AcdSeeError>;
+}
+
+impl FromAcdSee for String {
+    fn from_acdsee(value: &str) -> Result<Self, AcdSeeError> {
+        Ok(value.to_owned())
+    }
+}
+
+impl FromAcdSee for i32 {
+    fn from_acdsee(value: &str) -> Result<Self, AcdSeeError> {
+        Ok(value.parse().ok().unwrap_or(0))
+    }
+}
+
+impl FromAcdSee for bool {
+    fn from_acdsee(value: &str) -> Result<Self, AcdSeeError> {
+        Ok(value.to_ascii_lowercase() == "true")
+    }
+}
+
+impl FromAcdSee for chrono::NaiveDateTime {
+    fn from_acdsee(value: &str) -> Result<Self, AcdSeeError> {
+        Ok(value.parse()?)
+    }
+}
+
+impl FromAcdSee for TagHierarchy {
+    fn from_acdsee(value: &str) -> Result<Self, AcdSeeError> {
+        Ok(TagHierarchy::from_acdsee_categories(value)?)
+    }
+}
+
+impl<T: FromAcdSee> FromAcdSee for Option<T> {
+    fn from_acdsee(value: &str) -> Result<Self, AcdSeeError> {
+        if value.is_empty() {
+            Ok(None)
+        } else {
+            T::from_acdsee(value).map(Some)
+        }
+    }
+}
@@ -1,52 +1,49 @@
-use std::{convert::TryFrom, io::SeekFrom};
+use std::convert::TryFrom;
 
-use async_std::{fs::File, io::prelude::*};
 use thiserror::Error;
 use xml::reader::XmlEvent;
 
 use crate::{
     file::WritePacketError,
+    rt::*,
     xpacket::{XPacket, XPacketMut},
 };
 
-trait WriterExt {
-    fn write_all(&mut self, events: &[XmlEvent]) -> Result<(), xml::writer::Error>;
-}
-
-impl<W: std::io::Write> WriterExt for xml::writer::EventWriter<W> {
-    fn write_all(&mut self, events: &[XmlEvent]) -> Result<(), xml::writer::Error> {
-        for event in events {
-            if let Some(evt) = event.as_writer_event() {
-                self.write(evt)?;
-            }
-        }
-
-        Ok(())
-    }
-}
-
+/// Error reading the XMP data of a container
 #[derive(Debug, Error)]
 pub enum ContainerError {
+    /// I/O error
     #[error(transparent)]
     Io(#[from] std::io::Error),
+    /// Invalid XPacket
     #[error(transparent)]
     XPacketParse(#[from] crate::xpacket::XPacketParseError),
+    /// Invalid XMP data
     #[error(transparent)]
     XmpParse(#[from] crate::xmp::XmpParseError),
 }
 
+/// Error preparing the rewritten XPacket of a container
 #[derive(Debug, Error)]
 pub enum ContainerRewriteError {
+    /// I/O error
     #[error(transparent)]
     Io(#[from] std::io::Error),
+    /// Error serializing the XMP data
     #[error(transparent)]
     Emitter(xml::writer::Error),
+    /// The container has no XPacket
     #[error("missing xpacket")]
     MissingXPacket,
+    /// Invalid XPacket
     #[error(transparent)]
     XPacketParse(#[from] crate::xpacket::XPacketParseError),
+    /// The new XMP data does not fit in the existing XPacket
     #[error("not enough space for the new xpacket")]
     NotEnoughSpace,
+    /// The XPacket is marked as read-only
+    #[error("the xpacket is read-only")]
+    ReadOnlyPacket,
 }
 
 impl From<xml::writer::Error> for ContainerRewriteError {
@@ -58,12 +55,16 @@ impl From<xml::writer::Error> for ContainerRewriteError {
     }
 }
 
+/// Error writing a rewritten XPacket to a container
 #[derive(Debug, Error)]
 pub enum ContainerWriteError {
+    /// I/O error
     #[error(transparent)]
     Io(#[from] std::io::Error),
+    /// The container has no XPacket
     #[error("missing xpacket")]
     MissingXPacket,
+    /// The new packet does not have the size of the existing XPacket
     #[error("not enough space for the new xpacket")]
     NotEnoughSpace,
 }
@@ -78,6 +79,7 @@ impl From<WritePacketError> for ContainerWriteError {
     }
 }
 
+/// A file holding XMP data, either as a .xmp file or as an embedded XPacket
 pub struct Container {
     data: ContainerData,
 }
@@ -92,14 +94,16 @@ struct XmpData {
 }
 
 impl XmpData {
-    pub async fn read_xmp(&mut self) -> Result<Option<crate::xmp::XmpData>, ContainerError> {
+    pub async fn read_xmp(
+        &mut self,
+    ) -> Result<Option<(crate::xmp::XmpData, Vec<u8>)>, ContainerError> {
         self.fh.seek(SeekFrom::Start(0)).await?;
 
         let mut bytes = Vec::new();
         self.fh.read_to_end(&mut bytes).await?;
         let xmp = crate::xmp::XmpData::parse(&bytes)?;
 
-        Ok(Some(xmp))
+        Ok(Some((xmp, bytes)))
     }
 
     pub async fn prepare_write(
@@ -109,62 +113,84 @@ impl XmpData {
         // xmp file, we don't really need to do anything special size-wise to fit the data in the file
         let mut out = Vec::with_capacity(8192);
 
-        {
-            let mut writer = xml::writer::EventWriter::new_with_config(
-                &mut out,
-                xml::writer::EmitterConfig::new()
-                    .perform_indent(true)
-                    .indent_string(" ")
-                    .write_document_declaration(false),
-            );
-
-            // Write events
-            writer.write_all(events)?;
-        }
+        // Write events
+        crate::xmp::write_events_to(events, &mut out, true)?;
 
         Ok(out)
     }
 
-    pub async fn write(&mut self, packet: &[u8]) -> Result<(), ContainerWriteError> {
+    pub async fn is_unchanged(&mut self, packet: &[u8]) -> Result<bool, ContainerWriteError> {
+        self.fh.seek(SeekFrom::Start(0)).await?;
+        let mut current = Vec::new();
+        self.fh.read_to_end(&mut current).await?;
+
+        Ok(current == packet)
+    }
+
+    pub async fn write(&mut self, packet: &[u8]) -> Result<bool, ContainerWriteError> {
+        // Leave the file untouched if the contents did not change
+        if self.is_unchanged(packet).await? {
+            return Ok(false);
+        }
+
         // Seek to the beginning
         self.fh.seek(SeekFrom::Start(0)).await?;
 
         // Write the new contents
         self.fh.write_all(packet).await?;
+        self.fh.flush().await?;
 
         // Truncate the file
         self.fh.set_len(packet.len() as _).await?;
 
-        Ok(())
+        Ok(true)
     }
 }
 
 struct XPacketData {
     inner: crate::file::XPacketFile,
+    /// true if the file is a PNG image, whose chunks are checksummed
+    png: bool,
+}
+
+/// CRC-32 of `bytes`, as used by PNG chunks
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+
+    for &b in bytes {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
 }
 
 impl XPacketData {
-    pub async fn read_xmp(&mut self) -> Result<Option<crate::xmp::XmpData>, ContainerError> {
+    pub async fn read_xmp(
+        &mut self,
+    ) -> Result<Option<(crate::xmp::XmpData, Vec<u8>)>, ContainerError> {
         if let Some(packet_bytes) = self.inner.read_packet_bytes().await? {
             let xpacket = XPacket::try_from(&packet_bytes[..])?;
             let xmp = crate::xmp::XmpData::parse(&xpacket.body)?;
-            Ok(Some(xmp))
+            Ok(Some((xmp, xpacket.body.to_vec())))
         } else {
             Ok(None)
         }
     }
 
-    fn events_to_vec(
-        out: &mut Vec<u8>,
-        events: &[XmlEvent],
-        config: xml::writer::EmitterConfig,
-    ) -> Result<(), ContainerRewriteError> {
-        // Start with an empty buffer
-        out.clear();
-
-        let mut writer = xml::writer::EventWriter::new_with_config(out, config);
-        writer.write_all(events)?;
-        Ok(())
+    pub async fn estimated_free_space(&mut self) -> Result<Option<usize>, ContainerError> {
+        if let Some(packet_bytes) = self.inner.read_packet_bytes().await? {
+            let xpacket = XPacket::try_from(&packet_bytes[..])?;
+            Ok(Some(xpacket.body_capacity() - xpacket.used_bytes()))
+        } else {
+            Ok(None)
+        }
     }
 
     pub async fn prepare_write(
@@ -179,37 +205,28 @@ impl XPacketData {
             .read_packet_bytes()
             .await?
             .ok_or_else(|| ContainerRewriteError::MissingXPacket)?;
-        let xpacket = XPacketMut::try_from(&mut xpacket_bytes[..])?;
 
-        // Buffer for finding optimal settings
-        let mut out = Vec::with_capacity(xpacket.body.len() * 2);
+        // Read-only packets must not be modified in place
+        if XPacket::try_from(&xpacket_bytes[..])?.is_read_only() {
+            return Err(ContainerRewriteError::ReadOnlyPacket);
+        }
 
-        let emitter_configs = [
-            xml::writer::EmitterConfig::new()
-                .perform_indent(true)
-                .indent_string(" ")
-                .write_document_declaration(false),
-            xml::writer::EmitterConfig::new()
-                .perform_indent(false)
-                .write_document_declaration(false),
-        ];
-
-        for config in &emitter_configs {
-            // If we fail here, it's a XmlWriter error, so we always propagate
-            Self::events_to_vec(&mut out, events, config.clone())?;
+        let mut xpacket = XPacketMut::try_from(&mut xpacket_bytes[..])?;
 
-            if out.len() <= xpacket.body.len() - 2 {
-                // There is enough space in the existing packet for this config
+        // Buffer for finding optimal settings
+        let mut out = Vec::with_capacity(xpacket.body.len() * 2);
 
-                // Overwrite with padding and newlines
-                xpacket.body.fill(b' ');
-                xpacket.body[0] = b'\n';
-                *(xpacket.body.last_mut().unwrap()) = b'\n';
+        // Try the indented output first, then the compact one
+        for &pretty in &[true, false] {
+            // Start with an empty buffer
+            out.clear();
 
-                // Overwrite inner contents
-                xpacket.body[1..(1 + out.len())].copy_from_slice(&out);
+            // If we fail here, it's a XmlWriter error, so we always propagate
+            crate::xmp::write_events_to(events, &mut out, pretty)?;
 
-                // Return the full packet
+            if xpacket.replace_body(&out) {
+                // There was enough space in the existing packet for this config, return the full
+                // packet
                 return Ok(xpacket_bytes);
             }
         }
@@ -217,14 +234,71 @@ impl XPacketData {
         Err(ContainerRewriteError::NotEnoughSpace)
     }
 
-    pub async fn write(&mut self, packet: &[u8]) -> Result<(), ContainerWriteError> {
+    pub async fn is_unchanged(&mut self, packet: &[u8]) -> Result<bool, ContainerWriteError> {
+        Ok(self.inner.read_packet_bytes().await?.as_deref() == Some(packet))
+    }
+
+    pub async fn write(&mut self, packet: &[u8]) -> Result<bool, ContainerWriteError> {
+        // Leave the file untouched if the packet did not change
+        if self.is_unchanged(packet).await? {
+            return Ok(false);
+        }
+
         self.inner.write_packet_bytes(packet).await?;
-        Ok(())
+
+        if self.png {
+            self.update_png_crc().await?;
+        }
+
+        Ok(true)
+    }
+
+    /// Update the checksum of the PNG chunk holding the packet
+    async fn update_png_crc(&mut self) -> std::io::Result<()> {
+        let packet_start = match self.inner.span() {
+            Some(span) => span.start as u64,
+            None => return Ok(()),
+        };
+
+        let file = self.inner.file_mut();
+
+        // Walk the chunks, starting after the PNG signature
+        let mut offset = 8u64;
+        loop {
+            file.seek(SeekFrom::Start(offset)).await?;
+
+            let mut length = [0u8; 4];
+            file.read_exact(&mut length).await?;
+            let data_end = offset + 8 + u32::from_be_bytes(length) as u64;
+
+            if packet_start < data_end {
+                // This chunk holds the packet, the checksum covers its type and data
+                let mut chunk = vec![0; (data_end - offset - 4) as usize];
+                file.read_exact(&mut chunk).await?;
+
+                // The checksum follows the data
+                file.write_all(&crc32(&chunk).to_be_bytes()).await?;
+                file.flush().await?;
+
+                return Ok(());
+            }
+
+            // Skip the data and checksum
+            offset = data_end + 4;
+        }
     }
 }
 
 impl Container {
-    pub async fn open(mut file: async_std::fs::File) -> Result<Self, (std::io::Error, File)> {
+    /// Open the container stored in `file`
+    ///
+    /// The file is given back along with the error if it cannot be read.
+    #[tracing::instrument(level = "debug", skip(file), fields(size = tracing::field::Empty))]
+    pub async fn open(mut file: File) -> Result<Self, (std::io::Error, File)> {
+        if let Ok(metadata) = file.metadata().await {
+            tracing::Span::current().record("size", &metadata.len());
+        }
+
         // Seek back to the beginning
         match file.seek(SeekFrom::Start(0)).await {
             Ok(_) => {}
@@ -247,6 +321,7 @@ impl Container {
                     Ok(Self {
                         data: ContainerData::XPacket(XPacketData {
                             inner: crate::file::XPacketFile::open(file).await?,
+                            png: start_buf.starts_with(b"\x89PNG\r\n\x1a\n"),
                         }),
                     })
                 }
@@ -257,30 +332,94 @@ impl Container {
         }
     }
 
-    pub async fn read_xmp(&mut self) -> Result<Option<crate::xmp::XmpData>, ContainerError> {
-        match &mut self.data {
+    /// Read the XMP data from this container
+    ///
+    /// Returns the parsed data along with the original XMP bytes, or `None` if the container has
+    /// no XMP data.
+    #[tracing::instrument(level = "debug", skip(self), fields(bytes = tracing::field::Empty))]
+    pub async fn read_xmp(
+        &mut self,
+    ) -> Result<Option<(crate::xmp::XmpData, Vec<u8>)>, ContainerError> {
+        let result = match &mut self.data {
             ContainerData::Xmp(inner) => inner.read_xmp().await,
             ContainerData::XPacket(inner) => inner.read_xmp().await,
+        };
+
+        if let Ok(Some((_, bytes))) = &result {
+            tracing::Span::current().record("bytes", &bytes.len());
+        }
+
+        result
+    }
+
+    /// Number of padding bytes left in the XPacket of this container
+    ///
+    /// Returns `None` if the container has no size limit (XMP files) or no XPacket.
+    pub async fn estimated_free_space(&mut self) -> Result<Option<usize>, ContainerError> {
+        match &mut self.data {
+            ContainerData::Xmp(_) => Ok(None),
+            ContainerData::XPacket(inner) => inner.estimated_free_space().await,
         }
     }
 
+    /// Serialize `events` into a packet which can be passed to [`Container::write`]
+    #[tracing::instrument(
+        level = "debug",
+        skip(self, events),
+        fields(events_count = events.len(), bytes = tracing::field::Empty)
+    )]
     pub async fn prepare_write(
         &mut self,
         events: &[XmlEvent],
     ) -> Result<Vec<u8>, ContainerRewriteError> {
-        match &mut self.data {
+        let result = match &mut self.data {
             ContainerData::Xmp(inner) => inner.prepare_write(events).await,
             ContainerData::XPacket(inner) => inner.prepare_write(events).await,
+        };
+
+        if let Ok(packet) = &result {
+            tracing::Span::current().record("bytes", &packet.len());
         }
+
+        result
     }
 
-    pub async fn write(&mut self, packet: &[u8]) -> Result<(), ContainerWriteError> {
+    /// Returns `true` if the file already holds `packet`, so [`Container::write`] would leave it
+    /// untouched
+    pub async fn is_unchanged(&mut self, packet: &[u8]) -> Result<bool, ContainerWriteError> {
+        match &mut self.data {
+            ContainerData::Xmp(inner) => inner.is_unchanged(packet).await,
+            ContainerData::XPacket(inner) => inner.is_unchanged(packet).await,
+        }
+    }
+
+    /// Write a packet returned by [`Container::prepare_write`] to the file
+    ///
+    /// Returns `false` if the file was left untouched, because it already holds the same packet.
+    #[tracing::instrument(level = "debug", skip(self, packet), fields(bytes = packet.len()))]
+    pub async fn write(&mut self, packet: &[u8]) -> Result<bool, ContainerWriteError> {
         match &mut self.data {
             ContainerData::Xmp(inner) => inner.write(packet).await,
             ContainerData::XPacket(inner) => inner.write(packet).await,
         }
     }
 
+    /// Read all the bytes of the underlying file, not only its XMP data
+    pub async fn read_raw_bytes(&mut self) -> std::io::Result<Vec<u8>> {
+        let file = match &mut self.data {
+            ContainerData::Xmp(inner) => &mut inner.fh,
+            ContainerData::XPacket(inner) => inner.inner.file_mut(),
+        };
+
+        file.seek(SeekFrom::Start(0)).await?;
+
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes).await?;
+
+        Ok(bytes)
+    }
+
+    /// Returns the underlying file
     pub fn into_inner(self) -> File {
         match self.data {
             ContainerData::Xmp(inner) => inner.fh,
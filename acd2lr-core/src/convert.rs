@@ -0,0 +1,161 @@
+//! The conversion pipeline shared by the frontends
+//!
+//! [`convert_xmp`] rewrites parsed XMP data, and [`convert_bytes`] converts a whole file held in
+//! memory.
+
+use std::convert::TryFrom;
+
+use thiserror::Error;
+
+use crate::{
+    acdsee::{AcdSeeData, AcdSeeError, AcdSeeWarning, FieldMapping},
+    ns,
+    xmp::{rules, write_events_to, WriteError, XmpData, XmpParseError},
+    xpacket::{XPacket, XPacketMut, XPacketParseError},
+};
+
+/// Outcome of [`convert_xmp`]
+#[derive(Debug)]
+pub enum Conversion {
+    /// The XMP data has no ACDSee data to convert
+    NoAcdData,
+    /// The XMP data was already converted, and has no ACDSee data left
+    AlreadyConverted,
+    /// The rewritten XMP event stream
+    Converted(Vec<xml::reader::XmlEvent>),
+}
+
+/// Error converting a file held in memory
+#[derive(Debug, Error)]
+pub enum ConvertError {
+    /// The file is neither XMP data nor holds an XPacket
+    #[error("missing xpacket")]
+    MissingXPacket,
+    /// Invalid XPacket
+    #[error(transparent)]
+    XPacketParse(#[from] XPacketParseError),
+    /// The XPacket is marked as read-only
+    #[error("the xpacket is read-only")]
+    ReadOnlyPacket,
+    /// Invalid XMP data
+    #[error(transparent)]
+    XmpParse(#[from] XmpParseError),
+    /// None of the ACDSee fields could be decoded
+    #[error(transparent)]
+    AcdSee(#[from] AcdSeeError),
+    /// Error rewriting the XMP data
+    #[error(transparent)]
+    Write(#[from] WriteError),
+    /// The new XMP data does not fit in the existing XPacket
+    #[error("not enough space for the new xpacket")]
+    NotEnoughSpace,
+}
+
+/// Read the ACDSee data of `xmp` to convert
+///
+/// If some fields are invalid, the other ones are returned along with a warning listing the
+/// invalid fields. It fails only if none of the fields are usable.
+pub fn read_acdsee_data(xmp: &XmpData) -> Result<(AcdSeeData, Option<AcdSeeWarning>), AcdSeeError> {
+    match xmp.acdsee_data() {
+        Ok(acd) => Ok((acd, None)),
+        Err(error) => {
+            // Some fields are invalid, check if the other ones are still usable
+            let (acd, warning) = xmp.acdsee_data_partial();
+
+            if acd.is_empty() {
+                Err(error)
+            } else {
+                Ok((acd, Some(warning)))
+            }
+        }
+    }
+}
+
+/// Rewrite the ACDSee data `acd` of `xmp` to the fields selected by `mapping`
+pub fn convert_xmp(
+    xmp: &XmpData,
+    acd: &AcdSeeData,
+    mapping: &FieldMapping,
+) -> Result<Conversion, WriteError> {
+    let mut rules = acd.to_ruleset_with_mapping(mapping);
+    if rules.is_empty() {
+        // Tell apart files which were already migrated from files ACDSee never tagged
+        let converted = !xmp.has_namespace(ns::ACDSEE)
+            && (xmp.has_field(ns::LR, "hierarchicalSubject") || xmp.has_field(ns::XMP, "Rating"));
+
+        return Ok(if converted {
+            Conversion::AlreadyConverted
+        } else {
+            Conversion::NoAcdData
+        });
+    }
+
+    rules.push(rules::xmp_metadata_date());
+
+    Ok(Conversion::Converted(xmp.write_events(rules)?))
+}
+
+/// Location of the XPacket in `bytes`, if there is one
+fn find_xpacket(bytes: &[u8]) -> Option<std::ops::Range<usize>> {
+    let start = memchr::memmem::find(bytes, b"<?xpacket begin")?;
+    let footer = start + memchr::memmem::find(&bytes[start..], b"<?xpacket end")?;
+    let end = footer + memchr::memmem::find(&bytes[footer..], b"?>")? + 2;
+
+    Some(start..end)
+}
+
+/// Convert the ACDSee data of a file held in memory
+///
+/// `bytes` are the contents of an XMP sidecar, or of a file holding an XPacket. Returns the
+/// converted contents, or `None` if there is nothing to convert.
+pub fn convert_bytes(
+    bytes: &[u8],
+    mapping: &FieldMapping,
+) -> Result<Option<Vec<u8>>, ConvertError> {
+    let is_xmp = bytes.starts_with(b"<x:xmp");
+    let span = if is_xmp {
+        0..bytes.len()
+    } else {
+        find_xpacket(bytes).ok_or(ConvertError::MissingXPacket)?
+    };
+
+    let xmp = if is_xmp {
+        XmpData::parse(bytes)?
+    } else {
+        let xpacket = XPacket::try_from(&bytes[span.clone()])?;
+        if xpacket.is_read_only() {
+            return Err(ConvertError::ReadOnlyPacket);
+        }
+
+        XmpData::parse(xpacket.body)?
+    };
+
+    let (acd, _) = read_acdsee_data(&xmp)?;
+    let events = match convert_xmp(&xmp, &acd, mapping)? {
+        Conversion::Converted(events) => events,
+        Conversion::NoAcdData | Conversion::AlreadyConverted => return Ok(None),
+    };
+
+    if is_xmp {
+        // xmp file, the size of the output doesn't matter
+        let mut out = Vec::with_capacity(bytes.len());
+        write_events_to(&events, &mut out, true).map_err(WriteError::from)?;
+        return Ok(Some(out));
+    }
+
+    // xpacket container, the result needs to fit in the existing packet
+    let mut result = bytes.to_vec();
+    let mut xpacket = XPacketMut::try_from(&mut result[span])?;
+
+    let mut out = Vec::with_capacity(xpacket.body.len() * 2);
+    for &pretty in &[true, false] {
+        out.clear();
+        write_events_to(&events, &mut out, pretty).map_err(WriteError::from)?;
+
+        if xpacket.replace_body(&out) {
+            return Ok(Some(result));
+        }
+    }
+
+    Err(ConvertError::NotEnoughSpace)
+}
@@ -0,0 +1,143 @@
+//! C bindings
+//!
+//! Containers returned by [`acd2lr_open_file`] are owned by the caller, and must be released with
+//! [`acd2lr_close`]. Strings returned by these functions are also owned by the caller, and must be
+//! released with [`acd2lr_free_string`].
+
+use std::{
+    ffi::{CStr, CString},
+    os::raw::{c_char, c_int},
+    path::Path,
+    ptr,
+};
+
+use crate::{
+    acdsee::FieldMapping,
+    convert::{convert_xmp, read_acdsee_data, Conversion},
+    sync::SyncContainer,
+};
+
+/// An open file
+pub struct Container {
+    inner: SyncContainer,
+}
+
+impl Container {
+    fn apply_rules(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let (xmp, _) = self.inner.read_xmp()?.ok_or("missing xmp data")?;
+
+        let (acd, _) = read_acdsee_data(&xmp)?;
+        let events = match convert_xmp(&xmp, &acd, &FieldMapping::default())? {
+            Conversion::Converted(events) => events,
+            // Nothing to convert
+            Conversion::NoAcdData | Conversion::AlreadyConverted => return Ok(()),
+        };
+
+        let packet = self.inner.prepare_write(&events)?;
+        self.inner.write(&packet)?;
+
+        Ok(())
+    }
+}
+
+/// Open the file at `path`
+///
+/// Returns null if the file could not be opened.
+///
+/// # Safety
+///
+/// `path` must be null or a valid nul-terminated UTF-8 string.
+#[no_mangle]
+pub unsafe extern "C" fn acd2lr_open_file(path: *const c_char) -> *mut Container {
+    if path.is_null() {
+        return ptr::null_mut();
+    }
+
+    let path = match CStr::from_ptr(path).to_str() {
+        Ok(path) => Path::new(path),
+        Err(_) => return ptr::null_mut(),
+    };
+
+    // Fall back to read-only access, so at least the data can be read
+    match SyncContainer::open_rw(path).or_else(|_| SyncContainer::open(path)) {
+        Ok(inner) => Box::into_raw(Box::new(Container { inner })),
+        Err(error) => {
+            tracing::error!(%error, "failed to open {}", path.display());
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Read the ACDSee data of the file as a JSON string
+///
+/// Returns null if the file has no valid ACDSee data.
+///
+/// # Safety
+///
+/// `container` must be null or a pointer returned by [`acd2lr_open_file`].
+#[no_mangle]
+pub unsafe extern "C" fn acd2lr_get_acdsee_data(container: *mut Container) -> *mut c_char {
+    let container = match container.as_mut() {
+        Some(container) => container,
+        None => return ptr::null_mut(),
+    };
+
+    let data = match container.inner.read_xmp() {
+        Ok(Some((xmp, _))) => xmp.acdsee_data().ok(),
+        _ => None,
+    };
+
+    data.and_then(|data| serde_json::to_string(&data).ok())
+        .and_then(|json| CString::new(json).ok())
+        .map(CString::into_raw)
+        .unwrap_or_else(ptr::null_mut)
+}
+
+/// Convert the ACDSee data of the file, and write it back
+///
+/// Returns 0 on success, -1 on failure.
+///
+/// # Safety
+///
+/// `container` must be null or a pointer returned by [`acd2lr_open_file`].
+#[no_mangle]
+pub unsafe extern "C" fn acd2lr_apply_rules(container: *mut Container) -> c_int {
+    let container = match container.as_mut() {
+        Some(container) => container,
+        None => return -1,
+    };
+
+    match container.apply_rules() {
+        Ok(()) => 0,
+        Err(error) => {
+            tracing::error!(%error, "failed to apply rules");
+            -1
+        }
+    }
+}
+
+/// Close a file returned by [`acd2lr_open_file`]
+///
+/// # Safety
+///
+/// `container` must be null or a pointer returned by [`acd2lr_open_file`], which must not be used
+/// after this call.
+#[no_mangle]
+pub unsafe extern "C" fn acd2lr_close(container: *mut Container) {
+    if !container.is_null() {
+        drop(Box::from_raw(container));
+    }
+}
+
+/// Release a string returned by this library
+///
+/// # Safety
+///
+/// `s` must be null or a string returned by this library, which must not be used after this
+/// call.
+#[no_mangle]
+pub unsafe extern "C" fn acd2lr_free_string(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
@@ -1,12 +1,13 @@
 use std::ops::Range;
 
-use async_std::{
-    fs::File,
-    io::{prelude::*, BufReader, SeekFrom},
-};
-
 use thiserror::Error;
 
+use crate::rt::*;
+
+/// Size of the chunks read when looking for the XPacket markers
+const HAYSTACK_BUFFER_SIZE: usize = 64 * 1024;
+
+/// A file, along with the location of its XPacket
 #[derive(Debug)]
 pub struct XPacketFile {
     fh: File,
@@ -28,58 +29,69 @@ impl XPacketFile {
         }
     }
 
+    /// Find the next occurrence of `needle`, reading the file through `buffer`
+    ///
+    /// On success, the file is positioned at the start of the needle.
     async fn find_needle(
         buf: &mut BufReader<File>,
         needle: &[u8],
         buffer: &mut [u8],
     ) -> std::io::Result<Option<usize>> {
-        // Look for the packet beginning
+        debug_assert!(buffer.len() > needle.len());
+
+        let finder = memchr::memmem::Finder::new(needle);
+
+        // Position of buffer[0] in the file, and number of valid bytes in the buffer
+        let mut offset = buf.seek(SeekFrom::Current(0)).await? as usize;
+        let mut filled = 0;
+
         loop {
-            if let Ok(_) = buf.read_exact(buffer).await {
-                // read enough bytes
-
-                if let Some(idx) = memchr::memchr(needle[0], &buffer) {
-                    // Start char found in the buffer
-
-                    let left_in_haystack = buffer.len() - idx;
-                    if left_in_haystack >= needle.len() {
-                        // The needle may be at idx
-
-                        if &buffer[idx..(idx + needle.len())] == needle {
-                            // We found the needle at idx
-                            let needle_idx =
-                                buf.seek(SeekFrom::Current(0)).await? as usize - left_in_haystack;
-                            // Seek back
-                            buf.seek(SeekFrom::Start(needle_idx as _)).await?;
-                            return Ok(Some(needle_idx));
-                        } else {
-                            // We didn't find the needle at idx, seek back and repeat read
-                            buf.seek(SeekFrom::Current(-((left_in_haystack - 1) as i64)))
-                                .await?;
-                        }
-                    } else {
-                        // There's not enough left for the needle
-                        buf.seek(SeekFrom::Current(-(left_in_haystack as i64)))
-                            .await?;
-                    }
-                } else {
-                    // Start char not found in the entire buffer, so we can skip away
-                }
-            } else {
+            let read = buf.read(&mut buffer[filled..]).await?;
+            if read == 0 {
                 // eof
                 return Ok(None);
             }
+
+            filled += read;
+
+            if let Some(idx) = finder.find(&buffer[..filled]) {
+                // We found the needle, seek back to it
+                let needle_idx = offset + idx;
+                buf.seek(SeekFrom::Start(needle_idx as _)).await?;
+                return Ok(Some(needle_idx));
+            }
+
+            // Keep the end of the buffer, which may hold the beginning of the needle
+            let keep = (needle.len() - 1).min(filled);
+            buffer.copy_within(filled - keep..filled, 0);
+            offset += filled - keep;
+            filled = keep;
         }
     }
 
+    /// Returns the underlying file and the location of its XPacket
     pub fn into_inner(self) -> (File, Option<Range<usize>>) {
         (self.fh, self.span)
     }
 
+    /// Underlying file
     pub fn file(&self) -> &File {
         &self.fh
     }
 
+    /// Underlying file, for writing
+    pub fn file_mut(&mut self) -> &mut File {
+        &mut self.fh
+    }
+
+    /// Location of the XPacket in the file, if one was found
+    pub fn span(&self) -> Option<&Range<usize>> {
+        self.span.as_ref()
+    }
+
+    /// Open `file` and look for its XPacket
+    ///
+    /// The file is given back along with the error if it cannot be read.
     pub async fn open(mut file: File) -> Result<Self, (std::io::Error, File)> {
         // Start at the beginning
         match file.seek(SeekFrom::Start(0)).await {
@@ -88,22 +100,17 @@ impl XPacketFile {
                 let mut buf = BufReader::new(file);
 
                 // Buffer for looking for markers
-                let mut haystack_buffer: [u8; 128] = [0; 128];
+                let mut haystack_buffer = vec![0; HAYSTACK_BUFFER_SIZE];
 
                 // Find xpacket beginning
                 const XPACKET_BEGIN: &[u8] = b"<?xpacket begin";
-                let start = if let Some(start) = match Self::find_needle(
-                    &mut buf,
-                    &XPACKET_BEGIN,
-                    &mut haystack_buffer[..XPACKET_BEGIN.len()],
-                )
-                .await
-                {
-                    Ok(res) => res,
-                    Err(e) => {
-                        return Err((e, buf.into_inner()));
-                    }
-                } {
+                let start = if let Some(start) =
+                    match Self::find_needle(&mut buf, &XPACKET_BEGIN, &mut haystack_buffer).await {
+                        Ok(res) => res,
+                        Err(e) => {
+                            return Err((e, buf.into_inner()));
+                        }
+                    } {
                     start
                 } else {
                     return Ok(Self::no_xpacket(buf));
@@ -111,18 +118,13 @@ impl XPacketFile {
 
                 // Find xpacket end, starting at the current position
                 const XPACKET_END: &[u8] = b"<?xpacket end";
-                let _ = if let Some(_) = match Self::find_needle(
-                    &mut buf,
-                    &XPACKET_END,
-                    &mut haystack_buffer[..XPACKET_END.len()],
-                )
-                .await
-                {
-                    Ok(res) => res,
-                    Err(e) => {
-                        return Err((e, buf.into_inner()));
-                    }
-                } {
+                let _ = if let Some(_) =
+                    match Self::find_needle(&mut buf, &XPACKET_END, &mut haystack_buffer).await {
+                        Ok(res) => res,
+                        Err(e) => {
+                            return Err((e, buf.into_inner()));
+                        }
+                    } {
                     // nothing to do, we use this to advance the stream
                 } else {
                     return Ok(Self::no_xpacket(buf));
@@ -130,18 +132,13 @@ impl XPacketFile {
 
                 // After the start of the end marker, we want to find the ?> that marks the actual end
                 const BOUND_MARKER: &[u8] = b"?>";
-                let end = if let Some(end) = match Self::find_needle(
-                    &mut buf,
-                    &BOUND_MARKER,
-                    &mut haystack_buffer[..BOUND_MARKER.len()],
-                )
-                .await
-                {
-                    Ok(res) => res,
-                    Err(e) => {
-                        return Err((e, buf.into_inner()));
-                    }
-                } {
+                let end = if let Some(end) =
+                    match Self::find_needle(&mut buf, &BOUND_MARKER, &mut haystack_buffer).await {
+                        Ok(res) => res,
+                        Err(e) => {
+                            return Err((e, buf.into_inner()));
+                        }
+                    } {
                     // We want the end of the needle to return [start, end)
                     end + BOUND_MARKER.len()
                 } else {
@@ -156,9 +153,27 @@ impl XPacketFile {
         }
     }
 
+    /// Seek back to the beginning of the file
+    pub async fn rewind(&mut self) -> std::io::Result<()> {
+        self.fh.seek(SeekFrom::Start(0)).await?;
+        Ok(())
+    }
+
+    /// Seek to the beginning of the XPacket, returning its offset if one was found
+    pub async fn seek_to_packet_start(&mut self) -> std::io::Result<Option<u64>> {
+        if let Some(range) = &self.span {
+            let offset = range.start as u64;
+            self.fh.seek(SeekFrom::Start(offset)).await?;
+            Ok(Some(offset))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Read the bytes of the XPacket, or `None` if the file has no XPacket
     pub async fn read_packet_bytes(&mut self) -> std::io::Result<Option<Vec<u8>>> {
         if let Some(range) = self.span.clone() {
-            self.fh.seek(SeekFrom::Start(range.start as _)).await?;
+            self.seek_to_packet_start().await?;
 
             let mut buf = vec![0; range.len()];
             self.fh.read_exact(&mut buf[..]).await?;
@@ -169,6 +184,7 @@ impl XPacketFile {
         }
     }
 
+    /// Overwrite the XPacket with `new_bytes`, which must have the size of the existing packet
     pub async fn write_packet_bytes(&mut self, new_bytes: &[u8]) -> Result<(), WritePacketError> {
         if let Some(range) = self.span.clone() {
             if range.len() != new_bytes.len() {
@@ -176,10 +192,11 @@ impl XPacketFile {
             }
 
             // Seek to the beginning of the packet
-            self.fh.seek(SeekFrom::Start(range.start as _)).await?;
+            self.seek_to_packet_start().await?;
 
             // Write the packet
             self.fh.write_all(new_bytes).await?;
+            self.fh.flush().await?;
 
             Ok(())
         } else {
@@ -188,12 +205,16 @@ impl XPacketFile {
     }
 }
 
+/// Error writing an XPacket
 #[derive(Debug, Error)]
 pub enum WritePacketError {
+    /// I/O error
     #[error(transparent)]
     Io(#[from] std::io::Error),
+    /// The file has no XPacket
     #[error("no packet in this file")]
     NoPacket,
+    /// The new packet does not have the size of the existing one
     #[error("packet size does not match physical packet size")]
     WrongPacketSize,
 }
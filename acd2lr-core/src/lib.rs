@@ -1,27 +1,117 @@
-use std::collections::HashSet;
+//! Conversion of ACDSee metadata to the XMP fields used by Lightroom
+//!
+//! [`container::Container`] reads the XMP data of a file, [`xmp::XmpData`] decodes its ACDSee
+//! fields into [`acdsee::AcdSeeData`], and the rules returned by
+//! [`acdsee::AcdSeeData::to_ruleset`] rewrite them to the standard fields.
 
-use serde::Serialize;
+#![warn(missing_docs)]
 
+use std::collections::{BTreeMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(not(any(feature = "async-std", feature = "tokio", feature = "wasm")))]
+compile_error!("either the `async-std`, `tokio` or `wasm` feature must be enabled");
+
+/// ACDSee metadata
 pub mod acdsee;
+/// Files holding XMP data
+#[cfg(any(feature = "async-std", feature = "tokio"))]
 pub mod container;
+/// The conversion pipeline
+pub mod convert;
+/// C bindings
+#[cfg(feature = "ffi")]
+pub mod ffi;
+/// Locating XPackets in files
+#[cfg(any(feature = "async-std", feature = "tokio"))]
 pub mod file;
+/// XML namespaces
 pub mod ns;
+#[cfg(any(feature = "async-std", feature = "tokio"))]
+mod rt;
+/// Blocking API
+#[cfg(any(feature = "async-std", feature = "tokio"))]
+pub mod sync;
+/// WebAssembly bindings
+#[cfg(feature = "wasm")]
+pub mod wasm;
+/// Reading and rewriting XMP data
 pub mod xmp;
+/// XPacket parsing
 pub mod xpacket;
 
+/// XML parser configuration used by default for the XML read by this crate
+///
+/// Whitespace is trimmed and CDATA sections are read as text.
+pub fn default_config() -> xml::ParserConfig {
+    xml::ParserConfig::new()
+        .trim_whitespace(true)
+        .cdata_to_characters(true)
+}
+
 fn xml_reader<R: std::io::Read>(reader: R) -> xml::EventReader<R> {
-    xml::EventReader::new_with_config(
-        reader,
-        xml::ParserConfig::new()
-            .trim_whitespace(true)
-            .cdata_to_characters(true),
-    )
+    xml::EventReader::new_with_config(reader, default_config())
 }
 
 /// A tag in a given hierarchy
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "Vec<String>")]
 pub struct Tag(Vec<String>);
 
+impl Tag {
+    /// Create a tag from its components, from the root to the leaf
+    ///
+    /// The whitespace around each component is trimmed.
+    pub fn new<I, S>(components: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Self(
+            components
+                .into_iter()
+                .map(|component| component.as_ref().trim().to_owned())
+                .collect(),
+        )
+    }
+
+    /// Number of components in this tag
+    pub fn depth(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Last component of this tag, or an empty string for an empty tag
+    pub fn leaf(&self) -> &str {
+        self.0.last().map(|s| s.as_str()).unwrap_or("")
+    }
+
+    /// Components of this tag, from the root to the leaf
+    pub fn components(&self) -> &[String] {
+        &self.0[..]
+    }
+
+    /// Parent of this tag, or `None` for a root-level tag
+    pub fn parent(&self) -> Option<Tag> {
+        if self.depth() > 1 {
+            Some(Tag(self.0[..self.0.len() - 1].to_vec()))
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if this tag is a non-empty strict prefix of `other`
+    pub fn is_ancestor_of(&self, other: &Tag) -> bool {
+        !self.0.is_empty() && other.0.len() > self.0.len() && other.0.starts_with(&self.0)
+    }
+}
+
+impl From<Vec<String>> for Tag {
+    fn from(components: Vec<String>) -> Self {
+        Self::new(components)
+    }
+}
+
 impl std::ops::Deref for Tag {
     type Target = Vec<String>;
 
@@ -31,14 +121,18 @@ impl std::ops::Deref for Tag {
 }
 
 /// A tag hierarchy
-#[derive(Debug, Clone, Default, Serialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TagHierarchy(HashSet<Tag>);
 
 impl TagHierarchy {
+    /// Create an empty hierarchy
     pub fn new() -> Self {
         Self::default()
     }
 
+    /// Decode the XML categories stored in `acdsee:categories`
+    ///
+    /// Only the assigned categories are part of the hierarchy.
     pub fn from_acdsee_categories(value: &str) -> Result<Self, xml::reader::Error> {
         if value.is_empty() {
             return Ok(Self::default());
@@ -74,7 +168,7 @@ impl TagHierarchy {
                     if name.local_name == "Category" {
                         if let Some((_, assigned)) = tag_stack.last() {
                             if *assigned {
-                                set.insert(Tag(tag_stack.iter().map(|(s, _)| s.clone()).collect()));
+                                set.insert(Tag::new(tag_stack.iter().map(|(s, _)| s)));
                             }
 
                             tag_stack.pop();
@@ -93,6 +187,65 @@ impl TagHierarchy {
 
         Ok(Self(set))
     }
+
+    /// Serialize this hierarchy to the ACDSee categories format
+    ///
+    /// This is the inverse of [`TagHierarchy::from_acdsee_categories`].
+    pub fn to_acdsee_categories(&self) -> String {
+        if self.0.is_empty() {
+            return String::new();
+        }
+
+        // Build the category tree from the flat tag set
+        let mut root = CategoryNode::default();
+        for tag in &self.0 {
+            let node = tag.iter().fold(&mut root, |node, component| {
+                node.children.entry(component.as_str()).or_default()
+            });
+
+            node.assigned = true;
+        }
+
+        let mut writer = xml::EventWriter::new_with_config(
+            Vec::new(),
+            xml::EmitterConfig::new().write_document_declaration(false),
+        );
+
+        writer
+            .write(xml::writer::XmlEvent::start_element("Categories"))
+            .and_then(|_| root.write_children(&mut writer))
+            .and_then(|_| writer.write(xml::writer::XmlEvent::end_element()))
+            .expect("failed to write categories");
+
+        let out = writer.into_inner();
+        String::from_utf8(out).expect("xml writer produced invalid utf-8")
+    }
+}
+
+/// A node in the ACDSee category tree
+#[derive(Default)]
+struct CategoryNode<'a> {
+    assigned: bool,
+    children: BTreeMap<&'a str, CategoryNode<'a>>,
+}
+
+impl CategoryNode<'_> {
+    fn write_children<W: std::io::Write>(
+        &self,
+        writer: &mut xml::EventWriter<W>,
+    ) -> Result<(), xml::writer::Error> {
+        for (name, child) in &self.children {
+            writer.write(
+                xml::writer::XmlEvent::start_element("Category")
+                    .attr("Assigned", if child.assigned { "1" } else { "0" }),
+            )?;
+            writer.write(xml::writer::XmlEvent::characters(name))?;
+            child.write_children(writer)?;
+            writer.write(xml::writer::XmlEvent::end_element())?;
+        }
+
+        Ok(())
+    }
 }
 
 impl std::ops::Deref for TagHierarchy {
@@ -102,3 +255,12 @@ impl std::ops::Deref for TagHierarchy {
         &self.0
     }
 }
+
+impl<'a> IntoIterator for &'a TagHierarchy {
+    type Item = &'a Tag;
+    type IntoIter = std::collections::hash_set::Iter<'a, Tag>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
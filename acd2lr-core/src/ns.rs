@@ -23,3 +23,30 @@ pub const CRS: &str = "http://ns.adobe.com/camera-raw-settings/1.0/";
 
 /// lr namespace
 pub const LR: &str = "http://ns.adobe.com/lightroom/1.0/";
+
+/// exif namespace
+///
+/// The GPS properties (`exif:GPSLatitude`, `exif:GPSLongitude`, ...) belong to this namespace:
+/// there is no separate GPS namespace in XMP.
+pub const EXIF: &str = "http://ns.adobe.com/exif/1.0/";
+
+/// Conventional prefixes for the known namespaces
+pub const PREFIXES: &[(&str, &str)] = &[
+    (RDF, "rdf"),
+    (ACDSEE, "acdsee"),
+    (XMP, "xmp"),
+    (XMP_MM, "xmpMM"),
+    (ST_EVT, "stEvt"),
+    (DC, "dc"),
+    (CRS, "crs"),
+    (LR, "lr"),
+    (EXIF, "exif"),
+];
+
+/// Conventional prefix for the given namespace, if it is a known one
+pub fn prefix_for(namespace: &str) -> Option<&'static str> {
+    PREFIXES
+        .iter()
+        .find(|(ns, _)| *ns == namespace)
+        .map(|(_, prefix)| *prefix)
+}
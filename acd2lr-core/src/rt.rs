@@ -0,0 +1,33 @@
+//! Async runtime selection
+//!
+//! The file APIs use `async_std` by default, or `tokio` when the `tokio` feature is enabled.
+
+#[cfg(not(feature = "tokio"))]
+pub use async_std::{
+    fs::{File, OpenOptions},
+    io::{prelude::*, BufReader},
+};
+
+#[cfg(feature = "tokio")]
+pub use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader},
+};
+
+pub use std::io::SeekFrom;
+
+/// Run a future to completion on the current thread
+#[cfg(not(feature = "tokio"))]
+pub fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    async_std::task::block_on(future)
+}
+
+/// Run a future to completion on the current thread
+#[cfg(feature = "tokio")]
+pub fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build tokio runtime")
+        .block_on(future)
+}
@@ -0,0 +1,56 @@
+//! Blocking wrappers around the async file APIs
+
+use std::path::Path;
+
+use xml::reader::XmlEvent;
+
+use crate::{
+    container::{Container, ContainerError, ContainerRewriteError, ContainerWriteError},
+    rt::{block_on, File, OpenOptions},
+    xmp::XmpData,
+};
+
+/// A [`Container`] with synchronous methods
+pub struct SyncContainer {
+    inner: Container,
+}
+
+impl SyncContainer {
+    async fn from_file(file: File) -> std::io::Result<Self> {
+        let inner = Container::open(file).await.map_err(|(e, _)| e)?;
+        Ok(Self { inner })
+    }
+
+    /// Open the file at `path` for reading
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        block_on(async { Self::from_file(File::open(path).await?).await })
+    }
+
+    /// Open the file at `path` for reading and writing
+    pub fn open_rw(path: &Path) -> std::io::Result<Self> {
+        block_on(async {
+            let file = OpenOptions::new().read(true).write(true).open(path).await?;
+            Self::from_file(file).await
+        })
+    }
+
+    /// See [`Container::read_xmp`]
+    pub fn read_xmp(&mut self) -> Result<Option<(XmpData, Vec<u8>)>, ContainerError> {
+        block_on(self.inner.read_xmp())
+    }
+
+    /// See [`Container::prepare_write`]
+    pub fn prepare_write(&mut self, events: &[XmlEvent]) -> Result<Vec<u8>, ContainerRewriteError> {
+        block_on(self.inner.prepare_write(events))
+    }
+
+    /// See [`Container::write`]
+    pub fn write(&mut self, packet: &[u8]) -> Result<bool, ContainerWriteError> {
+        block_on(self.inner.write(packet))
+    }
+
+    /// Returns the underlying async container
+    pub fn into_inner(self) -> Container {
+        self.inner
+    }
+}
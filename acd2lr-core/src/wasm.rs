@@ -0,0 +1,20 @@
+//! WebAssembly bindings
+//!
+//! The conversion runs entirely in memory, on the bytes of the file to convert.
+
+use wasm_bindgen::prelude::*;
+
+use crate::{acdsee::FieldMapping, convert::convert_bytes};
+
+/// Convert the ACDSee metadata of a file
+///
+/// `bytes` are the contents of the file to convert, and the converted contents are returned.
+#[wasm_bindgen]
+pub fn convert_xmp(bytes: &[u8]) -> Result<Vec<u8>, JsValue> {
+    match convert_bytes(bytes, &FieldMapping::default()) {
+        Ok(Some(converted)) => Ok(converted),
+        // Nothing to convert
+        Ok(None) => Ok(bytes.to_vec()),
+        Err(error) => Err(JsValue::from_str(&error.to_string())),
+    }
+}
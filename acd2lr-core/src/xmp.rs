@@ -1,38 +1,181 @@
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 use thiserror::Error;
 use xml::name::OwnedName;
 
-use crate::{
-    acdsee::{AcdSeeData, AcdSeeError},
-    TagHierarchy,
-};
+use crate::acdsee::{AcdSeeData, AcdSeeError, AcdSeeWarning, FromAcdSee};
 
 mod rule;
 pub use rule::*;
 
+/// Key of a node in the position index: (namespace, local name)
+type NodeKey = (Option<String>, String);
+
+/// Parsed XMP data, kept as the stream of its XML events
 #[derive(Debug, Clone)]
 pub struct XmpData {
     events: Vec<xml::reader::XmlEvent>,
+    /// Index of the first StartElement event for each node name
+    positions: HashMap<NodeKey, usize>,
+    /// Namespaces declared by the rdf:Description nodes, which are merged when rewriting
+    declared_namespaces: xml::namespace::Namespace,
 }
 
+/// Error parsing XMP data
 #[derive(Debug, Error)]
 pub enum XmpParseError {
+    /// Invalid XML
     #[error(transparent)]
     Xml(#[from] xml::reader::Error),
 }
 
 impl XmpData {
+    /// Parse the XMP data in `source`
     pub fn parse(source: &[u8]) -> Result<XmpData, XmpParseError> {
-        Ok(Self {
-            events: crate::xml_reader(source)
-                .into_iter()
-                .collect::<Result<_, _>>()?,
-        })
+        Self::parse_with_config(source, crate::default_config())
+    }
+
+    /// Parse the XMP data in `source` with a custom XML parser configuration
+    ///
+    /// [`crate::default_config`] is the configuration used by [`XmpData::parse`].
+    #[tracing::instrument(
+        level = "debug",
+        skip(source, config),
+        fields(len = source.len(), events_count = tracing::field::Empty)
+    )]
+    pub fn parse_with_config(
+        source: &[u8],
+        config: xml::ParserConfig,
+    ) -> Result<XmpData, XmpParseError> {
+        let events: Vec<_> = xml::EventReader::new_with_config(source, config)
+            .into_iter()
+            .collect::<Result<_, _>>()?;
+        tracing::Span::current().record("events_count", &events.len());
+
+        Ok(Self::from_events(Self::expand_resource_nodes(events)))
+    }
+
+    fn from_events(events: Vec<xml::reader::XmlEvent>) -> Self {
+        let positions = Self::index_positions(&events);
+        let declared_namespaces = Self::description_namespaces(&events);
+
+        Self {
+            events,
+            positions,
+            declared_namespaces,
+        }
     }
 
-    fn acdsee_attr_value(&self, local_name: &str) -> Option<String> {
+    /// Rewrite `rdf:parseType="Resource"` nodes to the equivalent `rdf:Description` form
+    ///
+    /// `<x:a rdf:parseType="Resource">...</x:a>` becomes
+    /// `<x:a><rdf:Description>...</rdf:Description></x:a>`.
+    fn expand_resource_nodes(events: Vec<xml::reader::XmlEvent>) -> Vec<xml::reader::XmlEvent> {
+        let mut result = Vec::with_capacity(events.len());
+        // For each open element, the rdf:Description name to close with it, if any
+        let mut stack: Vec<Option<OwnedName>> = Vec::new();
+
+        for evt in events {
+            match evt {
+                xml::reader::XmlEvent::StartElement {
+                    name,
+                    mut attributes,
+                    namespace,
+                } => {
+                    let parse_type = attributes.iter().position(|attr| {
+                        attr.name.namespace.as_deref() == Some(crate::ns::RDF)
+                            && attr.name.local_name == "parseType"
+                            && attr.value == "Resource"
+                    });
+
+                    if let Some(index) = parse_type {
+                        let attr = attributes.remove(index);
+                        let description = OwnedName {
+                            local_name: "Description".to_owned(),
+                            namespace: attr.name.namespace,
+                            prefix: attr.name.prefix,
+                        };
+
+                        result.push(xml::reader::XmlEvent::StartElement {
+                            name,
+                            attributes,
+                            namespace: namespace.clone(),
+                        });
+                        result.push(xml::reader::XmlEvent::StartElement {
+                            name: description.clone(),
+                            attributes: Vec::new(),
+                            namespace,
+                        });
+
+                        stack.push(Some(description));
+                    } else {
+                        result.push(xml::reader::XmlEvent::StartElement {
+                            name,
+                            attributes,
+                            namespace,
+                        });
+
+                        stack.push(None);
+                    }
+                }
+                xml::reader::XmlEvent::EndElement { name } => {
+                    if let Some(Some(description)) = stack.pop() {
+                        result.push(xml::reader::XmlEvent::EndElement { name: description });
+                    }
+
+                    result.push(xml::reader::XmlEvent::EndElement { name });
+                }
+                other => {
+                    result.push(other);
+                }
+            }
+        }
+
+        result
+    }
+
+    fn index_positions(events: &[xml::reader::XmlEvent]) -> HashMap<NodeKey, usize> {
+        let mut positions = HashMap::new();
+
+        for (index, evt) in events.iter().enumerate() {
+            if let xml::reader::XmlEvent::StartElement { name, .. } = evt {
+                positions
+                    .entry((name.namespace.clone(), name.local_name.clone()))
+                    .or_insert(index);
+            }
+        }
+
+        positions
+    }
+
+    fn description_namespaces(events: &[xml::reader::XmlEvent]) -> xml::namespace::Namespace {
+        let mut namespaces = xml::namespace::Namespace::empty();
+
+        for evt in events {
+            if let xml::reader::XmlEvent::StartElement {
+                name, namespace, ..
+            } = evt
+            {
+                if name.namespace.as_deref() == Some(crate::ns::RDF)
+                    && name.local_name == "Description"
+                {
+                    namespaces.extend(namespace.into_iter());
+                }
+            }
+        }
+
+        namespaces
+    }
+
+    /// Index of the first StartElement event for the given node
+    fn position(&self, namespace: &str, local_name: &str) -> Option<usize> {
+        self.positions
+            .get(&(Some(namespace.to_owned()), local_name.to_owned()))
+            .copied()
+    }
+
+    fn attr_value(&self, namespace: &str, local_name: &str) -> Option<&str> {
         self.events.iter().find_map(|evt| {
             if let xml::reader::XmlEvent::StartElement {
                 name, attributes, ..
@@ -42,10 +185,10 @@ impl XmpData {
                     && name.local_name == "Description"
                 {
                     return attributes.iter().find_map(|attr| {
-                        if attr.name.namespace.as_deref() == Some(crate::ns::ACDSEE)
+                        if attr.name.namespace.as_deref() == Some(namespace)
                             && attr.name.local_name == local_name
                         {
-                            return Some(attr.value.to_owned());
+                            return Some(attr.value.as_str());
                         }
 
                         None
@@ -57,112 +200,166 @@ impl XmpData {
         })
     }
 
-    fn acdsee_tag_value(&self, local_name: &str) -> Option<String> {
+    fn acdsee_attr_value(&self, local_name: &str) -> Option<&str> {
+        self.attr_value(crate::ns::ACDSEE, local_name)
+    }
+
+    /// Returns true if the given field is present, either as a node or as an attribute
+    pub fn has_field(&self, namespace: &str, local_name: &str) -> bool {
+        self.position(namespace, local_name).is_some()
+            || self.attr_value(namespace, local_name).is_some()
+    }
+
+    /// Returns true if any node or attribute belongs to the given namespace
+    pub fn has_namespace(&self, namespace: &str) -> bool {
+        self.positions
+            .keys()
+            .any(|(ns, _)| ns.as_deref() == Some(namespace))
+            || self.events.iter().any(|evt| {
+                matches!(evt, xml::reader::XmlEvent::StartElement { attributes, .. }
+                    if attributes
+                        .iter()
+                        .any(|attr| attr.name.namespace.as_deref() == Some(namespace)))
+            })
+    }
+
+    /// Value of the given ACDSee field, borrowed from the XMP data
+    ///
+    /// Surrounding whitespace is ignored, and blank values are treated as missing.
+    pub fn acdsee_tag_value_ref<'a>(&'a self, local_name: &str) -> Option<&'a str> {
         let result = self.acdsee_attr_value(local_name).or_else(|| {
-            self.events
-                .iter()
-                .skip_while(|evt| {
-                    // Look for the right StartElement
-                    if let xml::reader::XmlEvent::StartElement { name, .. } = evt {
-                        !(name.namespace.as_deref() == Some(crate::ns::ACDSEE)
-                            && name.local_name == local_name)
-                    } else {
-                        true
-                    }
-                })
-                .skip(1)
-                .next()
+            self.position(crate::ns::ACDSEE, local_name)
+                .and_then(|index| self.events.get(index + 1))
                 .and_then(|evt| match evt {
-                    xml::reader::XmlEvent::Characters(value) => Some(value.to_owned()),
+                    xml::reader::XmlEvent::Characters(value) => Some(value.as_str()),
                     _ => None,
                 })
         });
 
+        let result =
+            result
+                .map(str::trim)
+                .and_then(|value| if value.is_empty() { None } else { Some(value) });
+
         tracing::trace!(value = ?result, "acdsee tag {}", local_name);
         result
     }
 
+    fn acdsee_tag_value(&self, local_name: &str) -> Option<String> {
+        self.acdsee_tag_value_ref(local_name).map(str::to_owned)
+    }
+
     fn acdsee_bag_value(&self, local_name: &str) -> Vec<String> {
-        self.events
-            .iter()
-            .skip_while(|evt| {
-                // Look for the right StartElement
-                if let xml::reader::XmlEvent::StartElement { name, .. } = evt {
-                    !(name.namespace.as_deref() == Some(crate::ns::ACDSEE)
-                        && name.local_name == local_name)
-                } else {
-                    true
+        let start = match self.position(crate::ns::ACDSEE, local_name) {
+            Some(start) => start,
+            None => return Vec::new(),
+        };
+
+        // ACDSee may write the same item twice, so only keep the first occurrence
+        let mut seen = HashSet::new();
+        let mut items = Vec::new();
+
+        // Depth relative to the field node: the field is at level 1, the rdf:Bag at level 2 and
+        // its rdf:li items at level 3
+        let mut level = 0;
+        for evt in &self.events[start..] {
+            match evt {
+                xml::reader::XmlEvent::StartElement { .. } => {
+                    level += 1;
                 }
-            })
-            .take_while(|evt| {
-                // Look for the right EndElement
-                if let xml::reader::XmlEvent::EndElement { name, .. } = evt {
-                    !(name.namespace.as_deref() == Some(crate::ns::ACDSEE)
-                        && name.local_name == local_name)
-                } else {
-                    true
+                xml::reader::XmlEvent::EndElement { .. } => {
+                    level -= 1;
+
+                    if level == 0 {
+                        // End of the field node
+                        break;
+                    }
                 }
-            })
-            .filter_map(|item| {
-                if let xml::reader::XmlEvent::Characters(chs) = item {
-                    Some(chs.to_owned())
-                } else {
-                    None
+                xml::reader::XmlEvent::Characters(chs) if level == 3 => {
+                    if seen.insert(chs.as_str()) {
+                        items.push(chs.to_owned());
+                    }
                 }
-            })
-            .collect()
+                _ => {}
+            }
+        }
+
+        items
     }
 
+    fn acdsee_value<T: FromAcdSee>(&self, local_name: &str) -> Result<Option<T>, AcdSeeError> {
+        self.acdsee_tag_value(local_name)
+            .map(|value| T::from_acdsee(&value))
+            .transpose()
+    }
+
+    /// Decode the ACDSee fields of this XMP data
+    ///
+    /// Fails if any of the fields is invalid, see [`XmpData::acdsee_data_partial`] to read the valid
+    /// fields only.
     pub fn acdsee_data(&self) -> Result<AcdSeeData, AcdSeeError> {
         Ok(AcdSeeData {
-            caption: self.acdsee_tag_value("caption"),
-            categories: self
-                .acdsee_tag_value("categories")
-                .map(|value| TagHierarchy::from_acdsee_categories(&value))
-                .transpose()?,
+            caption: self.acdsee_value("caption")?,
+            categories: self.acdsee_value("categories")?,
             datetime: self
-                .acdsee_tag_value("datetime")
-                .and_then(|val| if val.is_empty() { None } else { Some(val) })
-                .map(|val| val.parse())
-                .transpose()?,
-            author: self.acdsee_tag_value("author"),
-            rating: self
-                .acdsee_tag_value("rating")
-                .map(|value| value.parse().ok().unwrap_or(0)),
-            notes: self.acdsee_tag_value("notes"),
-            tagged: self
-                .acdsee_tag_value("tagged")
-                .map(|value| value.to_ascii_lowercase() == "true"),
-            collections: self.acdsee_tag_value("collections"),
+                .acdsee_value::<Option<chrono::NaiveDateTime>>("datetime")?
+                .flatten(),
+            author: self.acdsee_value("author")?,
+            rating: self.acdsee_value("rating")?,
+            notes: self.acdsee_value("notes")?,
+            tagged: self.acdsee_value("tagged")?,
+            collections: self.acdsee_value("collections")?,
             keywords: self.acdsee_bag_value("keywords"),
         })
     }
 
+    /// Read the ACDSee data, skipping invalid fields
+    ///
+    /// Returns the valid fields, along with the errors for the invalid ones.
+    pub fn acdsee_data_partial(&self) -> (AcdSeeData, AcdSeeWarning) {
+        let mut warning = AcdSeeWarning::default();
+
+        let data = AcdSeeData {
+            caption: warning.check("caption", self.acdsee_value("caption")),
+            categories: warning.check("categories", self.acdsee_value("categories")),
+            datetime: warning.check(
+                "datetime",
+                self.acdsee_value::<Option<chrono::NaiveDateTime>>("datetime")
+                    .map(Option::flatten),
+            ),
+            author: warning.check("author", self.acdsee_value("author")),
+            rating: warning.check("rating", self.acdsee_value("rating")),
+            notes: warning.check("notes", self.acdsee_value("notes")),
+            tagged: warning.check("tagged", self.acdsee_value("tagged")),
+            collections: warning.check("collections", self.acdsee_value("collections")),
+            keywords: self.acdsee_bag_value("keywords"),
+        };
+
+        (data, warning)
+    }
+
+    /// Write the given data to the ACDSee fields of a copy of this XMP data
+    pub fn set_acdsee_data(&self, data: &AcdSeeData) -> Result<XmpData, WriteError> {
+        Ok(Self::from_events(
+            self.write_events(data.to_acdsee_ruleset())?,
+        ))
+    }
+
+    /// Apply `rules` to this XMP data, returning the rewritten event stream
+    ///
+    /// Rules for nodes which do not exist yet add them to the first `rdf:Description` block.
     pub fn write_events(
         &self,
         rules: Vec<RewriteRule>,
     ) -> Result<Vec<xml::reader::XmlEvent>, WriteError> {
-        let mut evts = Vec::with_capacity(self.events.len());
+        let span = tracing::debug_span!(
+            "write_events",
+            rules_count = rules.len(),
+            events_count = self.events.len()
+        );
+        let _enter = span.enter();
 
-        // Find all namespaces
-        let mut all_namespaces = xml::namespace::Namespace::empty();
-        for evt in &self.events {
-            match evt {
-                xml::reader::XmlEvent::StartElement {
-                    name,
-                    attributes: _,
-                    namespace,
-                } => {
-                    if name.namespace.as_deref() == Some(crate::ns::RDF)
-                        && name.local_name == "Description"
-                    {
-                        // A rdf::Description start
-                        all_namespaces.extend(namespace.into_iter());
-                    }
-                }
-                _ => {}
-            }
-        }
+        let mut evts = Vec::with_capacity(self.events.len());
 
         // Collect all rdf:Description attributes
         let mut all_attributes = Vec::new();
@@ -216,8 +413,9 @@ impl XmpData {
             }
         };
 
-        // Add all rules to a hash map to speed up lookups
-        let mut rules: HashMap<_, _> = rules
+        // Add all rules to a sorted map to speed up lookups, and so leftover rules are always
+        // applied in the same order
+        let mut rules: BTreeMap<_, _> = rules
             .into_iter()
             .filter_map(|rule| {
                 // Check if we can process an attribute
@@ -280,7 +478,7 @@ impl XmpData {
                                     .drain(..)
                                     .map(|a| (*a).to_owned())
                                     .collect(),
-                                namespace: all_namespaces.clone(),
+                                namespace: self.declared_namespaces.clone(),
                             });
 
                             state = State::InDescription(1);
@@ -369,7 +567,9 @@ impl XmpData {
                             if name.namespace.as_deref() == Some(crate::ns::RDF)
                                 && name.local_name == "Description" =>
                         {
-                            // Start description, we're skipping this
+                            // Start description, we're skipping this: all top-level
+                            // rdf:Description blocks are merged into the first one, so rules
+                            // still apply to nodes from the following blocks
                             state = State::InDescription(1);
                             pending_end_element.take();
                         }
@@ -377,7 +577,7 @@ impl XmpData {
                             if let Some(evt) = pending_end_element.take() {
                                 // Before we close the rdf:Description, we need to make sure we ran
                                 // all required rules
-                                for (_, rule) in rules.drain() {
+                                for (_, rule) in std::mem::take(&mut rules) {
                                     if rule.required() {
                                         register_rule_namespace(&mut evts[..], &rule);
 
@@ -399,12 +599,128 @@ impl XmpData {
             }
         }
 
+        // Use the conventional prefixes for known namespaces
+        let prefixes = conventional_prefixes(&evts);
+        for evt in &mut evts {
+            normalize_prefixes(evt, &prefixes);
+        }
+
         Ok(evts)
     }
+
+    /// Serialize this XMP data, with indentation
+    pub fn to_xml_string(&self) -> Result<String, WriteError> {
+        let events = self.write_events(vec![])?;
+
+        let mut out = Vec::with_capacity(8192);
+        write_events_to(&events, &mut out, true)?;
+
+        Ok(String::from_utf8(out).expect("xml writer produced invalid utf-8"))
+    }
+}
+
+/// Conventional prefixes to use for the known namespaces bound in `events`, by URI
+///
+/// A known namespace keeps its original prefix if the document binds its conventional prefix to
+/// another URI.
+fn conventional_prefixes(events: &[xml::reader::XmlEvent]) -> HashMap<String, &'static str> {
+    let bindings: HashSet<(&str, &str)> = events
+        .iter()
+        .filter_map(|evt| match evt {
+            xml::reader::XmlEvent::StartElement { namespace, .. } => Some(namespace),
+            _ => None,
+        })
+        .flat_map(|namespace| namespace.into_iter())
+        .collect();
+
+    bindings
+        .iter()
+        .filter_map(|&(_, uri)| {
+            let prefix = crate::ns::prefix_for(uri)?;
+            let taken = bindings
+                .iter()
+                .any(|&(other_prefix, other_uri)| other_prefix == prefix && other_uri != uri);
+
+            if taken {
+                None
+            } else {
+                Some((uri.to_owned(), prefix))
+            }
+        })
+        .collect()
+}
+
+/// Replace the prefixes of known namespaces in the given event by their conventional values
+fn normalize_prefixes(evt: &mut xml::reader::XmlEvent, prefixes: &HashMap<String, &'static str>) {
+    let normalize_name = |name: &mut OwnedName| {
+        if let Some(prefix) = name.namespace.as_deref().and_then(|uri| prefixes.get(uri)) {
+            name.prefix = Some((*prefix).to_owned());
+        }
+    };
+
+    match evt {
+        xml::reader::XmlEvent::StartElement {
+            name,
+            attributes,
+            namespace,
+        } => {
+            normalize_name(name);
+
+            for attr in attributes {
+                normalize_name(&mut attr.name);
+            }
+
+            let mut normalized = xml::namespace::Namespace::empty();
+            for (prefix, uri) in &*namespace {
+                normalized.put(prefixes.get(uri).copied().unwrap_or(prefix), uri);
+            }
+
+            *namespace = normalized;
+        }
+        xml::reader::XmlEvent::EndElement { name } => {
+            normalize_name(name);
+        }
+        _ => {}
+    }
 }
 
+/// Serialize XML events to the given writer
+///
+/// When `pretty` is set, the output is indented. Otherwise, it is written as compactly as
+/// possible.
+pub fn write_events_to<W: std::io::Write>(
+    events: &[xml::reader::XmlEvent],
+    writer: W,
+    pretty: bool,
+) -> Result<(), xml::writer::Error> {
+    let config = if pretty {
+        xml::writer::EmitterConfig::new()
+            .perform_indent(true)
+            .indent_string(" ")
+            .write_document_declaration(false)
+    } else {
+        xml::writer::EmitterConfig::new()
+            .perform_indent(false)
+            .write_document_declaration(false)
+    };
+
+    let mut writer = xml::writer::EventWriter::new_with_config(writer, config);
+    for event in events {
+        if let Some(evt) = event.as_writer_event() {
+            writer.write(evt)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Error rewriting XMP data
 #[derive(Debug, Error)]
 pub enum WriteError {
+    /// A rewrite rule failed on the given node
     #[error("rule failed for node {:?}", 0)]
     RuleFailed(OwnedName),
+    /// Error serializing the XML events
+    #[error(transparent)]
+    Emitter(#[from] xml::writer::Error),
 }
@@ -1,6 +1,7 @@
 use thiserror::Error;
 use xml::name::OwnedName;
 
+/// A rule rewriting the value of an XMP node
 pub struct RewriteRule {
     node_namespace: Option<&'static str>,
     node_name: &'static str,
@@ -10,13 +11,19 @@ pub struct RewriteRule {
     action: Box<dyn RewriteAction>,
 }
 
+/// Error running a rewrite rule
 #[derive(Debug, Error)]
 pub enum RewriteRuleError {
+    /// The node is an attribute, and the rule only rewrites elements
     #[error("attributes are not supported by this rule")]
     Unsupported,
 }
 
 impl RewriteRule {
+    /// Create a rule running `action` on the node with the given name
+    ///
+    /// If `allow_attribute` is set, the node may be stored as an attribute of `rdf:Description`.
+    /// If `required` is set, the node is added if it does not exist yet.
     pub fn new(
         node_namespace: Option<&'static str>,
         node_name: &'static str,
@@ -35,6 +42,7 @@ impl RewriteRule {
         }
     }
 
+    /// Qualified name of the node this rule applies to
     pub fn name(&self) -> OwnedName {
         if let Some(ns) = self.namespace() {
             xml::name::OwnedName::qualified::<_, _, _>(self.local_name(), ns, Some(self.prefix()))
@@ -43,30 +51,37 @@ impl RewriteRule {
         }
     }
 
+    /// Namespace of the node this rule applies to
     pub fn namespace(&self) -> Option<&'static str> {
         self.node_namespace
     }
 
+    /// Local name of the node this rule applies to
     pub fn local_name(&self) -> &'static str {
         self.node_name
     }
 
+    /// Prefix used when this rule adds the node
     pub fn prefix(&self) -> &'static str {
         self.node_prefix
     }
 
+    /// Returns true if the node may be stored as an attribute
     pub fn allow_attribute(&self) -> bool {
         self.allow_attribute
     }
 
+    /// Returns true if the node is added when it does not exist
     pub fn required(&self) -> bool {
         self.required
     }
 
+    /// Returns true if this rule applies to the node `name`
     pub fn matches(&self, name: &xml::name::Name) -> bool {
         name.local_name == self.node_name && name.namespace.as_deref() == self.node_namespace
     }
 
+    /// Rewrite the events of the node, from its start to its end element
     pub fn run(
         &self,
         input: &[&xml::reader::XmlEvent],
@@ -78,13 +93,18 @@ impl RewriteRule {
             .map(|_| output)
     }
 
+    /// Rewrite the value of the node, when it is stored as an attribute
     pub fn run_attribute(&self, input: &str) -> Result<String, RewriteRuleError> {
         // Rewrite contents
         self.action.rewrite_attribute(self, input)
     }
 }
 
-pub trait RewriteAction: Send {
+/// The rewriting done by a [`RewriteRule`]
+pub trait RewriteAction: Send + Sync {
+    /// Write the new events of the node to `output`
+    ///
+    /// `input` holds the events of the existing node, or is empty if the node is being added.
     fn rewrite(
         &self,
         rule: &RewriteRule,
@@ -92,6 +112,7 @@ pub trait RewriteAction: Send {
         output: &mut Vec<xml::reader::XmlEvent>,
     ) -> Result<(), RewriteRuleError>;
 
+    /// Returns the new value of the node when it is stored as an attribute
     fn rewrite_attribute(
         &self,
         _rule: &RewriteRule,
@@ -101,6 +122,7 @@ pub trait RewriteAction: Send {
     }
 }
 
+/// Set the node to the current date and time
 pub struct SetToCurrentDateTime;
 
 impl SetToCurrentDateTime {
@@ -146,14 +168,95 @@ impl RewriteAction for SetToCurrentDateTime {
     }
 }
 
+/// Set a node to a simple text value
+pub struct SetSimpleValue {
+    value: String,
+}
+
+impl SetSimpleValue {
+    /// Create an action setting the node to `value`
+    pub fn new(value: String) -> Self {
+        Self { value }
+    }
+}
+
+impl RewriteAction for SetSimpleValue {
+    fn rewrite(
+        &self,
+        rule: &RewriteRule,
+        input: &[&xml::reader::XmlEvent],
+        output: &mut Vec<xml::reader::XmlEvent>,
+    ) -> Result<(), RewriteRuleError> {
+        let name = if let Some(xml::reader::XmlEvent::StartElement { name, .. }) = input.get(0) {
+            name.to_owned()
+        } else {
+            rule.name()
+        };
+
+        output.push(xml::reader::XmlEvent::StartElement {
+            name: name.clone(),
+            attributes: vec![],
+            namespace: xml::namespace::Namespace::empty(),
+        });
+
+        if !self.value.is_empty() {
+            output.push(xml::reader::XmlEvent::Characters(self.value.clone()));
+        }
+
+        output.push(xml::reader::XmlEvent::EndElement { name });
+
+        Ok(())
+    }
+
+    fn rewrite_attribute(
+        &self,
+        _rule: &RewriteRule,
+        _input: &str,
+    ) -> Result<String, RewriteRuleError> {
+        Ok(self.value.clone())
+    }
+}
+
+/// Remove the node
+///
+/// Attributes cannot be removed, so the rule using this action must not allow them.
+pub struct DeleteNode;
+
+impl RewriteAction for DeleteNode {
+    fn rewrite(
+        &self,
+        _rule: &RewriteRule,
+        _input: &[&xml::reader::XmlEvent],
+        _output: &mut Vec<xml::reader::XmlEvent>,
+    ) -> Result<(), RewriteRuleError> {
+        Ok(())
+    }
+}
+
+/// Set the node to an RDF container (`Seq`, `Alt` or `Bag`) of values
 pub struct SetRdfList {
     ty: &'static str,
-    values: Vec<String>,
+    values: Vec<(Option<String>, String)>,
 }
 
 impl SetRdfList {
+    /// Create a list of the given container type
     pub fn new(ty: &'static str, values: Vec<String>) -> Self {
-        Self { ty, values }
+        Self {
+            ty,
+            values: values.into_iter().map(|value| (None, value)).collect(),
+        }
+    }
+
+    /// Create a list where each item is a `(xml:lang, value)` pair
+    pub fn with_lang(ty: &'static str, values: Vec<(String, String)>) -> Self {
+        Self {
+            ty,
+            values: values
+                .into_iter()
+                .map(|(lang, value)| (Some(lang), value))
+                .collect(),
+        }
     }
 }
 
@@ -194,10 +297,22 @@ impl RewriteAction for SetRdfList {
 
         let rdf_li = rdf_node("li");
 
-        for item in &self.values {
+        for (lang, item) in &self.values {
+            let attributes = lang
+                .iter()
+                .map(|lang| xml::attribute::OwnedAttribute {
+                    name: OwnedName::qualified(
+                        "lang",
+                        xml::namespace::NS_XML_URI,
+                        Some(xml::namespace::NS_XML_PREFIX),
+                    ),
+                    value: lang.clone(),
+                })
+                .collect();
+
             output.push(xml::reader::XmlEvent::StartElement {
                 name: rdf_li.clone(),
-                attributes: vec![],
+                attributes,
                 namespace: xml::namespace::Namespace::empty(),
             });
 
@@ -216,11 +331,13 @@ impl RewriteAction for SetRdfList {
     }
 }
 
+/// Constructors for the rules used by the conversion
 pub mod rules {
     use crate::TagHierarchy;
 
     use super::*;
 
+    /// Set `xmp:MetadataDate` to the current date
     pub fn xmp_metadata_date() -> RewriteRule {
         RewriteRule::new(
             Some(crate::ns::XMP),
@@ -232,6 +349,24 @@ pub mod rules {
         )
     }
 
+    /// Set a node to a simple text value
+    pub fn set_simple_value(
+        namespace: &'static str,
+        prefix: &'static str,
+        name: &'static str,
+        value: String,
+    ) -> RewriteRule {
+        RewriteRule::new(
+            Some(namespace),
+            name,
+            prefix,
+            true,
+            true,
+            SetSimpleValue::new(value),
+        )
+    }
+
+    /// Set a node to an ordered list of values
     pub fn set_rdf_seq(
         namespace: &'static str,
         prefix: &'static str,
@@ -248,6 +383,7 @@ pub mod rules {
         )
     }
 
+    /// Set a node to a list of alternative values
     pub fn set_rdf_alt(
         namespace: &'static str,
         prefix: &'static str,
@@ -264,6 +400,24 @@ pub mod rules {
         )
     }
 
+    /// Set a node to a list of `(xml:lang, value)` alternatives
+    pub fn set_rdf_alt_with_lang(
+        namespace: &'static str,
+        prefix: &'static str,
+        name: &'static str,
+        values: Vec<(String, String)>,
+    ) -> RewriteRule {
+        RewriteRule::new(
+            Some(namespace),
+            name,
+            prefix,
+            false,
+            true,
+            SetRdfList::with_lang("Alt", values),
+        )
+    }
+
+    /// Set a node to an unordered list of values
     pub fn set_rdf_bag(
         namespace: &'static str,
         prefix: &'static str,
@@ -280,28 +434,110 @@ pub mod rules {
         )
     }
 
+    /// Set `dc:title`
     pub fn set_dc_title(value: String) -> RewriteRule {
-        set_rdf_alt(crate::ns::DC, "dc", "title", vec![value])
+        set_rdf_alt_with_lang(
+            crate::ns::DC,
+            "dc",
+            "title",
+            vec![("x-default".to_string(), value)],
+        )
     }
 
+    /// Set `dc:subject`
     pub fn set_dc_subject(values: Vec<String>) -> RewriteRule {
         set_rdf_bag(crate::ns::DC, "dc", "subject", values)
     }
 
+    /// Set `dc:description`
     pub fn set_dc_description(value: String) -> RewriteRule {
-        set_rdf_alt(crate::ns::DC, "dc", "description", vec![value])
+        set_rdf_alt_with_lang(
+            crate::ns::DC,
+            "dc",
+            "description",
+            vec![("x-default".to_string(), value)],
+        )
     }
 
+    /// Set `dc:creator`
     pub fn set_dc_creator(value: String) -> RewriteRule {
         set_rdf_seq(crate::ns::DC, "dc", "creator", vec![value])
     }
 
+    /// Set `xmp:BaseURL`, to record the location of the original file
+    pub fn set_xmp_base_url(url: &str) -> RewriteRule {
+        set_simple_value(crate::ns::XMP, "xmp", "BaseURL", url.to_owned())
+    }
+
+    /// Remove `xmpMM:History`, which may grow until it fills the XPacket
+    pub fn strip_xmp_mm_history() -> RewriteRule {
+        RewriteRule::new(
+            Some(crate::ns::XMP_MM),
+            "History",
+            "xmpMM",
+            false,
+            false,
+            DeleteNode,
+        )
+    }
+
+    /// Remove the fields written by ACDSee
+    ///
+    /// Only the fields stored as elements are removed, since [`DeleteNode`] cannot remove
+    /// attributes.
+    pub fn strip_acdsee_fields() -> Vec<RewriteRule> {
+        [
+            "caption",
+            "datetime",
+            "author",
+            "rating",
+            "notes",
+            "tagged",
+            "categories",
+            "collections",
+            "keywords",
+        ]
+        .iter()
+        .map(|&name| {
+            RewriteRule::new(
+                Some(crate::ns::ACDSEE),
+                name,
+                "acdsee",
+                false,
+                false,
+                DeleteNode,
+            )
+        })
+        .collect()
+    }
+
+    /// Set `lr:hierarchicalSubject` from a tag hierarchy
     pub fn set_lr_hierarchical_subject(tags: &TagHierarchy) -> RewriteRule {
+        set_lr_hierarchical_subject_with_separator(tags, '|')
+    }
+
+    /// Set `lr:hierarchicalSubject` from a tag hierarchy, separating levels with `separator`
+    pub fn set_lr_hierarchical_subject_with_separator(
+        tags: &TagHierarchy,
+        separator: char,
+    ) -> RewriteRule {
+        let separator = separator.to_string();
+
         set_rdf_bag(
             crate::ns::LR,
             "lr",
             "hierarchicalSubject",
-            tags.iter().map(|tag| tag[..].join("|")).collect(),
+            tags.iter().map(|tag| tag[..].join(&separator)).collect(),
         )
     }
+
+    /// Set an `acdsee:` field
+    pub fn set_acdsee_value(name: &'static str, value: String) -> RewriteRule {
+        set_simple_value(crate::ns::ACDSEE, "acdsee", name, value)
+    }
+
+    /// Set `acdsee:keywords`
+    pub fn set_acdsee_keywords(values: Vec<String>) -> RewriteRule {
+        set_rdf_bag(crate::ns::ACDSEE, "acdsee", "keywords", values)
+    }
 }
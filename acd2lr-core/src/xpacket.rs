@@ -1,28 +1,63 @@
 use std::convert::TryFrom;
 use thiserror::Error;
 
+/// An XPacket, split into its header, body and footer
 #[derive(Debug, Clone, Copy)]
 pub struct XPacket<'p> {
+    /// `<?xpacket begin=... ?>` processing instruction
     pub header: &'p [u8],
+    /// XMP data, including trailing padding
     pub body: &'p [u8],
+    /// `<?xpacket end=... ?>` processing instruction
     pub footer: &'p [u8],
 }
 
+impl XPacket<'_> {
+    /// Returns true if the footer of this packet marks it as read-only
+    pub fn is_read_only(&self) -> bool {
+        memchr::memmem::find(self.footer, b"end='r'").is_some()
+            || memchr::memmem::find(self.footer, b"end=\"r\"").is_some()
+    }
+
+    /// Size of the body of this packet, including padding
+    pub fn body_capacity(&self) -> usize {
+        self.body.len()
+    }
+
+    /// Size of the body of this packet, excluding trailing padding
+    pub fn used_bytes(&self) -> usize {
+        self.body
+            .iter()
+            .rposition(|b| !b.is_ascii_whitespace())
+            .map(|i| i + 1)
+            .unwrap_or(0)
+    }
+}
+
+/// A mutable XPacket, split into its header, body and footer
 #[derive(Debug)]
 pub struct XPacketMut<'p> {
+    /// `<?xpacket begin=... ?>` processing instruction
     pub header: &'p mut [u8],
+    /// XMP data, including trailing padding
     pub body: &'p mut [u8],
+    /// `<?xpacket end=... ?>` processing instruction
     pub footer: &'p mut [u8],
 }
 
+/// Error parsing an XPacket
 #[derive(Debug, Error)]
 pub enum XPacketParseError {
+    /// The bytes do not start with an XPacket header
     #[error("missing xpacket header")]
     MissingHeader,
+    /// No XPacket footer was found
     #[error("missing xpacket footer")]
     MissingFooter,
+    /// The XPacket header is not terminated
     #[error("missing xpacket header boundary")]
     MissingHeaderBoundary,
+    /// The XPacket footer is not terminated
     #[error("missing xpacket footer boundary")]
     MissingFooterBoundary,
 }
@@ -35,7 +70,12 @@ fn get_offsets(value: &[u8]) -> Result<(usize, usize), XPacketParseError> {
         return Err(XPacketParseError::MissingHeader);
     }
 
-    const FOOTER_MARKERS: &[&[u8]] = &[b"<?xpacket end=\"w\"?>", b"<?xpacket end='w'?>"];
+    const FOOTER_MARKERS: &[&[u8]] = &[
+        b"<?xpacket end=\"w\"?>",
+        b"<?xpacket end='w'?>",
+        b"<?xpacket end=\"r\"?>",
+        b"<?xpacket end='r'?>",
+    ];
 
     // Check we have a footer at the end
     let body_end = FOOTER_MARKERS
@@ -74,6 +114,27 @@ impl<'p> TryFrom<&'p [u8]> for XPacket<'p> {
     }
 }
 
+impl XPacketMut<'_> {
+    /// Replace the body of this packet with `content`, padded with spaces
+    ///
+    /// Returns false, leaving the packet untouched, if `content` does not fit in the packet.
+    pub fn replace_body(&mut self, content: &[u8]) -> bool {
+        if self.body.len() < 2 || content.len() > self.body.len() - 2 {
+            return false;
+        }
+
+        // Overwrite with padding and newlines
+        self.body.fill(b' ');
+        self.body[0] = b'\n';
+        *(self.body.last_mut().unwrap()) = b'\n';
+
+        // Overwrite inner contents
+        self.body[1..(1 + content.len())].copy_from_slice(content);
+
+        true
+    }
+}
+
 impl<'p> TryFrom<&'p mut [u8]> for XPacketMut<'p> {
     type Error = XPacketParseError;
 
@@ -0,0 +1,35 @@
+use acd2lr_core::{
+    acdsee::FieldMapping,
+    convert::{convert_bytes, ConvertError},
+};
+use test_env_log::test;
+
+#[test]
+fn test_convert_bytes_in_place() {
+    let bytes = &include_bytes!("data/acdsee_data.nef")[..];
+
+    let converted = convert_bytes(bytes, &FieldMapping::default())
+        .expect("failed to convert")
+        .expect("missing converted data");
+
+    // The packet is rewritten in place
+    assert_eq!(converted.len(), bytes.len());
+    assert!(String::from_utf8_lossy(&converted).contains("Vacances|Allos"));
+}
+
+#[test]
+fn test_convert_bytes_nothing_to_convert() {
+    let bytes = &include_bytes!("data/lightroom_data.xpacket")[..];
+
+    assert!(convert_bytes(bytes, &FieldMapping::default())
+        .expect("failed to convert")
+        .is_none());
+}
+
+#[test]
+fn test_convert_bytes_missing_xpacket() {
+    assert!(matches!(
+        convert_bytes(b"not an xpacket", &FieldMapping::default()),
+        Err(ConvertError::MissingXPacket)
+    ));
+}
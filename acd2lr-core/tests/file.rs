@@ -1,7 +1,15 @@
 use std::convert::TryFrom;
 use std::path::Path;
 
-use acd2lr_core::{file::XPacketFile, xmp::XmpData, xpacket::XPacket};
+use acd2lr_core::{
+    acdsee::AcdSeeData,
+    container::Container,
+    file::XPacketFile,
+    sync::SyncContainer,
+    xmp::{rules, XmpData},
+    xpacket::XPacket,
+    TagHierarchy,
+};
 use async_std::{fs::File, task::block_on};
 use test_env_log::test;
 
@@ -37,3 +45,195 @@ fn test_multi_description() {
         test_file("tests/data/test_cat_multi.jpg").await;
     });
 }
+
+#[test]
+fn test_sync_container() {
+    let mut container =
+        SyncContainer::open(Path::new("tests/data/test_cat.jpg")).expect("failed to open file");
+    let (xmp, _) = container
+        .read_xmp()
+        .expect("failed to read xmp")
+        .expect("missing xmp data");
+
+    eprintln!(
+        "{:#?}",
+        xmp.acdsee_data().expect("failed to parse acdsee data")
+    );
+}
+
+#[test]
+fn test_raw_pipeline() {
+    // Minimal TIFF-based raw file, with the ACDSee xpacket in its XMP tag
+    let mut container =
+        SyncContainer::open(Path::new("tests/data/acdsee_data.nef")).expect("failed to open file");
+    let (xmp, _) = container
+        .read_xmp()
+        .expect("failed to read xmp")
+        .expect("missing xmp data");
+
+    let mut rules = xmp
+        .acdsee_data()
+        .expect("failed to parse acdsee data")
+        .to_ruleset();
+    assert!(!rules.is_empty());
+    rules.push(rules::xmp_metadata_date());
+
+    let events = xmp.write_events(rules).expect("failed to rewrite xmp");
+    container
+        .prepare_write(&events)
+        .expect("failed to prepare packet");
+}
+
+#[test]
+fn test_write_unchanged() {
+    let path = std::env::temp_dir().join("acd2lr_test_write_unchanged.nef");
+    std::fs::copy("tests/data/acdsee_data.nef", &path).expect("failed to copy test file");
+
+    let mut container = SyncContainer::open_rw(&path).expect("failed to open file");
+    let (xmp, _) = container
+        .read_xmp()
+        .expect("failed to read xmp")
+        .expect("missing xmp data");
+
+    let events = xmp
+        .write_events(xmp.acdsee_data().unwrap().to_ruleset())
+        .expect("failed to rewrite xmp");
+    let packet = container
+        .prepare_write(&events)
+        .expect("failed to prepare packet");
+
+    // The first write changes the file, writing the same packet again does not
+    assert!(container.write(&packet).expect("failed to write packet"));
+    assert!(!container.write(&packet).expect("failed to write packet"));
+
+    std::fs::remove_file(&path).ok();
+}
+
+#[test]
+fn test_read_raw_bytes() {
+    block_on(async {
+        let path = "tests/data/test_cat.jpg";
+        let file = File::open(path).await.expect("failed to open file");
+        let mut container = Container::open(file)
+            .await
+            .map_err(|(e, _)| e)
+            .expect("failed to open container");
+
+        // Reading the XMP data first must not change the raw bytes
+        container.read_xmp().await.expect("failed to read xmp");
+
+        assert_eq!(
+            container
+                .read_raw_bytes()
+                .await
+                .expect("failed to read bytes"),
+            std::fs::read(path).unwrap()
+        );
+    });
+}
+
+#[test]
+fn test_is_unchanged() {
+    block_on(async {
+        let path = "tests/data/acdsee_data.xpacket";
+        let file = File::open(path).await.expect("failed to open file");
+        let mut container = Container::open(file)
+            .await
+            .map_err(|(e, _)| e)
+            .expect("failed to open container");
+
+        let mut packet = std::fs::read(path).unwrap();
+        assert!(container
+            .is_unchanged(&packet)
+            .await
+            .expect("failed to compare packets"));
+
+        packet[packet.len() / 2] ^= 1;
+        assert!(!container
+            .is_unchanged(&packet)
+            .await
+            .expect("failed to compare packets"));
+    });
+}
+
+#[test]
+fn test_jpeg_round_trip() {
+    let path = std::env::temp_dir().join("acd2lr_test_jpeg_round_trip.jpg");
+    std::fs::copy("tests/data/test_cat.jpg", &path).expect("failed to copy test file");
+
+    let read_xmp = || {
+        SyncContainer::open(&path)
+            .expect("failed to open file")
+            .read_xmp()
+            .expect("failed to read xmp")
+            .expect("missing xmp data")
+            .0
+    };
+
+    let write = |events: &[xml::reader::XmlEvent]| {
+        let mut container = SyncContainer::open_rw(&path).expect("failed to open file");
+        let packet = container
+            .prepare_write(events)
+            .expect("failed to prepare packet");
+        container.write(&packet).expect("failed to write packet");
+    };
+
+    // The fixture was exported by Lightroom, so tag it the way ACDSee would first
+    let data = AcdSeeData {
+        rating: Some(4),
+        categories: Some(
+            TagHierarchy::from_acdsee_categories(
+                "<Categories><Category Assigned=\"1\">Animals<Category Assigned=\"1\">Cats\
+                 </Category></Category></Categories>",
+            )
+            .unwrap(),
+        ),
+        keywords: vec!["Kitten".to_owned()],
+        ..Default::default()
+    };
+
+    let xmp = read_xmp();
+    write(
+        &xmp.write_events(data.to_acdsee_ruleset())
+            .expect("failed to rewrite xmp"),
+    );
+
+    let xmp = read_xmp();
+    assert_eq!(
+        xmp.acdsee_data().expect("failed to parse acdsee data"),
+        data
+    );
+
+    // Run the conversion
+    let mut rules = data.to_ruleset();
+    rules.push(rules::xmp_metadata_date());
+    write(&xmp.write_events(rules).expect("failed to rewrite xmp"));
+
+    let converted = read_xmp().to_xml_string().expect("failed to serialize xmp");
+    assert!(converted.contains("Animals|Cats"));
+    assert!(converted.contains(">Kitten<"));
+
+    std::fs::remove_file(&path).ok();
+}
+
+#[test]
+fn test_xpacket_across_chunks() {
+    // The XPacket starts just before the end of the first chunk read by XPacketFile::open
+    let offset = 64 * 1024 - 5;
+    let mut bytes = vec![b'<'; offset];
+    bytes.extend_from_slice(include_bytes!("data/acdsee_data.xpacket"));
+
+    let path = std::env::temp_dir().join("acd2lr_test_xpacket_across_chunks.bin");
+    std::fs::write(&path, &bytes).expect("failed to write test file");
+
+    block_on(async {
+        let file = XPacketFile::open(File::open(&path).await.unwrap())
+            .await
+            .map_err(|(error, _)| error)
+            .expect("failed to open file");
+
+        assert_eq!(file.span().map(|span| span.start), Some(offset));
+    });
+
+    std::fs::remove_file(&path).ok();
+}
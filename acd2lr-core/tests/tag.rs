@@ -0,0 +1,48 @@
+use acd2lr_core::{Tag, TagHierarchy};
+use test_env_log::test;
+
+#[test]
+fn test_categories_trimmed() {
+    let tags = TagHierarchy::from_acdsee_categories(
+        "<Categories><Category Assigned=\"0\"> Animals <Category Assigned=\"1\">Cats </Category>\
+         </Category><Category Assigned=\"0\">Animals<Category Assigned=\"1\"> Cats</Category>\
+         </Category></Categories>",
+    )
+    .unwrap();
+
+    assert_eq!(tags.len(), 1);
+
+    let tag = tags.iter().next().unwrap();
+    assert_eq!(
+        tag.components(),
+        &["Animals".to_owned(), "Cats".to_owned()][..]
+    );
+}
+
+#[test]
+fn test_categories_round_trip() {
+    let tags = TagHierarchy::from_acdsee_categories(
+        "<Categories><Category Assigned=\"1\">Animals<Category Assigned=\"1\">Cats</Category>\
+         <Category Assigned=\"1\">Dogs &amp; Wolves</Category></Category>\
+         <Category Assigned=\"0\">Places<Category Assigned=\"1\">Paris</Category></Category>\
+         </Categories>",
+    )
+    .unwrap();
+
+    assert_eq!(tags.len(), 4);
+
+    let categories = tags.to_acdsee_categories();
+    assert_eq!(
+        TagHierarchy::from_acdsee_categories(&categories).unwrap(),
+        tags
+    );
+}
+
+#[test]
+fn test_tag_trimmed() {
+    assert_eq!(
+        Tag::new(vec![" Animals", "Cats "]),
+        Tag::new(vec!["Animals", "Cats"])
+    );
+    assert_eq!(Tag::from(vec![" Paris ".to_owned()]).leaf(), "Paris");
+}
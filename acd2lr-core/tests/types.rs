@@ -0,0 +1,23 @@
+use acd2lr_core::{
+    acdsee::{AcdSeeData, AcdSeeError, AcdSeeWarning},
+    container::{ContainerError, ContainerRewriteError, ContainerWriteError},
+    xmp::{RewriteRule, WriteError, XmpData},
+    TagHierarchy,
+};
+use test_env_log::test;
+
+fn check<T: Send + Sync>() {}
+
+#[test]
+fn test_send_sync() {
+    check::<AcdSeeData>();
+    check::<AcdSeeError>();
+    check::<AcdSeeWarning>();
+    check::<TagHierarchy>();
+    check::<XmpData>();
+    check::<RewriteRule>();
+    check::<WriteError>();
+    check::<ContainerError>();
+    check::<ContainerRewriteError>();
+    check::<ContainerWriteError>();
+}
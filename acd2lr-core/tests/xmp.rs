@@ -1,9 +1,12 @@
 use std::{convert::TryFrom, io::prelude::*, path::Path};
 
 use acd2lr_core::{
+    acdsee::{AcdSeeData, FieldMapping, XmpTarget},
     file::XPacketFile,
-    xmp::{rules, XmpData},
+    ns,
+    xmp::{rules, write_events_to, XmpData},
     xpacket::XPacket,
+    TagHierarchy,
 };
 use async_std::{fs::File, task::block_on};
 use test_env_log::test;
@@ -40,6 +43,19 @@ fn test_xpacket_lightroom() {
     test_xpacket(&include_bytes!("data/lightroom_data.xpacket")[..]);
 }
 
+#[test]
+fn test_xpacket_read_only() {
+    let writable = test_xpacket(&include_bytes!("data/acdsee_data.xpacket")[..]);
+    assert!(!writable.is_read_only());
+
+    let read_only = test_xpacket(
+        &br#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/"/>
+<?xpacket end="r"?>"#[..],
+    );
+    assert!(read_only.is_read_only());
+}
+
 #[test]
 fn test_xmp_acdsee() {
     test_xmp(&include_bytes!("data/acdsee_data.xpacket")[..]);
@@ -75,19 +91,7 @@ async fn test_rewrite(p: impl AsRef<Path>) {
     let events = events.unwrap();
 
     let mut out = Vec::with_capacity(packet.body.len());
-    let mut writer = xml::writer::EventWriter::new_with_config(
-        &mut out,
-        xml::writer::EmitterConfig::new()
-            .perform_indent(true)
-            .indent_string(" ")
-            .write_document_declaration(false),
-    );
-
-    for event in events {
-        if let Some(evt) = event.as_writer_event() {
-            writer.write(evt).unwrap();
-        }
-    }
+    write_events_to(&events, &mut out, true).unwrap();
 
     std::io::stderr().write_all(&out[..]).unwrap();
     eprintln!();
@@ -127,3 +131,318 @@ fn test_rewrite_multi() {
         test_rewrite("tests/data/test_cat_multi.jpg").await;
     });
 }
+
+#[test]
+fn test_parse_type_resource() {
+    let xmp = XmpData::parse(
+        br#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description xmlns:xmpMM="http://ns.adobe.com/xap/1.0/mm/">
+   <xmpMM:DerivedFrom rdf:parseType="Resource">
+    <xmpMM:DocumentID>abc</xmpMM:DocumentID>
+   </xmpMM:DerivedFrom>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>"#,
+    )
+    .unwrap();
+
+    let out = xmp.to_xml_string().unwrap();
+
+    assert!(!out.contains("parseType"));
+    assert_eq!(out.matches("<rdf:Description").count(), 2);
+}
+
+#[test]
+fn test_rewrite_second_description() {
+    let xmp = XmpData::parse(
+        br#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description xmlns:xmp="http://ns.adobe.com/xap/1.0/" xmp:Rating="3"/>
+  <rdf:Description xmlns:dc="http://purl.org/dc/elements/1.1/">
+   <dc:title><rdf:Alt><rdf:li>old title</rdf:li></rdf:Alt></dc:title>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>"#,
+    )
+    .unwrap();
+
+    let events = xmp
+        .write_events(vec![rules::set_dc_title("new title".to_owned())])
+        .unwrap();
+
+    let mut out = Vec::new();
+    write_events_to(&events, &mut out, true).unwrap();
+    let out = String::from_utf8(out).unwrap();
+
+    assert!(!out.contains("old title"));
+    assert_eq!(out.matches("new title").count(), 1);
+    assert!(out.contains(r#"<rdf:li xml:lang="x-default">new title</rdf:li>"#));
+}
+
+#[test]
+fn test_normalize_prefixes() {
+    let xmp = XmpData::parse(
+        br#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description xmlns:xap="http://ns.adobe.com/xap/1.0/" xap:Rating="3"/>
+ </rdf:RDF>
+</x:xmpmeta>"#,
+    )
+    .unwrap();
+
+    let out = xmp.to_xml_string().unwrap();
+
+    assert!(!out.contains("xap:"));
+    assert!(out.contains("xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\""));
+    assert!(out.contains("xmp:Rating=\"3\""));
+}
+
+#[test]
+fn test_normalize_prefixes_taken() {
+    // dc is bound to a custom namespace, Dublin Core has to keep its own prefix
+    let xmp = XmpData::parse(
+        br#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description xmlns:dc="http://example.com/dc/" xmlns:dce="http://purl.org/dc/elements/1.1/" dc:custom="a" dce:format="image/jpeg"/>
+ </rdf:RDF>
+</x:xmpmeta>"#,
+    )
+    .unwrap();
+
+    let out = xmp.to_xml_string().unwrap();
+
+    assert_eq!(out.matches("xmlns:dc=").count(), 1);
+    assert!(out.contains("xmlns:dc=\"http://example.com/dc/\""));
+    assert!(out.contains("xmlns:dce=\"http://purl.org/dc/elements/1.1/\""));
+    assert!(out.contains("dc:custom=\"a\""));
+    assert!(out.contains("dce:format=\"image/jpeg\""));
+}
+
+#[test]
+fn test_set_acdsee_data() {
+    let xpacket = XPacket::try_from(&include_bytes!("data/lightroom_data.xpacket")[..]).unwrap();
+    let xmp = XmpData::parse(xpacket.body).unwrap();
+
+    let data = AcdSeeData {
+        caption: Some("Caption".to_owned()),
+        rating: Some(3),
+        tagged: Some(true),
+        keywords: vec!["Kw1".to_owned(), "Kw2".to_owned()],
+        ..Default::default()
+    };
+
+    let acdsee = xmp.set_acdsee_data(&data).unwrap().acdsee_data().unwrap();
+
+    assert_eq!(acdsee.caption, data.caption);
+    assert_eq!(acdsee.rating, data.rating);
+    assert_eq!(acdsee.tagged, data.tagged);
+    assert_eq!(acdsee.keywords, data.keywords);
+}
+
+#[test]
+fn test_field_mapping() {
+    let xpacket = XPacket::try_from(&include_bytes!("data/acdsee_data.xpacket")[..]).unwrap();
+    let xmp = XmpData::parse(xpacket.body).unwrap();
+
+    let data = AcdSeeData {
+        caption: Some("Mapped caption".to_owned()),
+        ..Default::default()
+    };
+
+    let mapping = FieldMapping {
+        caption_to: vec![XmpTarget::XMP_DESCRIPTION],
+        ..Default::default()
+    };
+
+    let rewritten = xmp
+        .write_events(data.to_ruleset_with_mapping(&mapping))
+        .unwrap();
+    let mut out = Vec::new();
+    write_events_to(&rewritten, &mut out, false).unwrap();
+    let out = String::from_utf8(out).unwrap();
+
+    assert!(out.contains("<xmp:Description>Mapped caption</xmp:Description>"));
+    assert!(!out.contains("<dc:title"));
+    assert_eq!(
+        "xmp:Description".parse::<XmpTarget>().unwrap(),
+        XmpTarget::XMP_DESCRIPTION
+    );
+}
+
+#[test]
+fn test_tag_separator() {
+    let xpacket = XPacket::try_from(&include_bytes!("data/acdsee_data.xpacket")[..]).unwrap();
+    let xmp = XmpData::parse(xpacket.body).unwrap();
+
+    let data = AcdSeeData {
+        categories: Some(
+            TagHierarchy::from_acdsee_categories(
+                "<Categories><Category Assigned=\"1\">Animals<Category Assigned=\"1\">Cats\
+                 </Category></Category></Categories>",
+            )
+            .unwrap(),
+        ),
+        ..Default::default()
+    };
+
+    let mapping = FieldMapping {
+        tag_separator: '/',
+        ..Default::default()
+    };
+
+    let rewritten = xmp
+        .write_events(data.to_ruleset_with_mapping(&mapping))
+        .unwrap();
+    let mut out = Vec::new();
+    write_events_to(&rewritten, &mut out, false).unwrap();
+    let out = String::from_utf8(out).unwrap();
+
+    assert!(out.contains("Animals/Cats"));
+    assert!(!out.contains("Animals|Cats"));
+}
+
+#[test]
+fn test_parse_with_config() {
+    let xpacket = XPacket::try_from(&include_bytes!("data/acdsee_data.xpacket")[..]).unwrap();
+
+    let default = XmpData::parse(xpacket.body).unwrap();
+    let untrimmed = XmpData::parse_with_config(
+        xpacket.body,
+        acd2lr_core::default_config().trim_whitespace(false),
+    )
+    .unwrap();
+
+    assert!(default.has_namespace(ns::ACDSEE));
+    assert!(untrimmed.has_namespace(ns::ACDSEE));
+}
+
+#[test]
+fn test_round_trip_without_rules() {
+    let xpacket = XPacket::try_from(&include_bytes!("data/acdsee_data.xpacket")[..]).unwrap();
+    let xmp = XmpData::parse(xpacket.body).unwrap();
+
+    let rewritten = xmp.write_events(vec![]).unwrap();
+    let mut out = Vec::new();
+    write_events_to(&rewritten, &mut out, false).unwrap();
+
+    let reparsed = XmpData::parse(&out).unwrap();
+
+    let data = xmp.acdsee_data().unwrap();
+    assert!(!data.is_empty());
+    assert_eq!(data, reparsed.acdsee_data().unwrap());
+}
+
+#[test]
+fn test_acdsee_tag_value_ref() {
+    let xpacket = XPacket::try_from(&include_bytes!("data/acdsee_data.xpacket")[..]).unwrap();
+    let xmp = XmpData::parse(xpacket.body).unwrap();
+
+    let data = xmp.acdsee_data().unwrap();
+
+    assert_eq!(xmp.acdsee_tag_value_ref("caption"), data.caption.as_deref());
+    assert_eq!(xmp.acdsee_tag_value_ref("missing"), None);
+}
+
+#[test]
+fn test_keywords_with_nested_description() {
+    let xmp = XmpData::parse(
+        br#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description xmlns:acdsee="http://ns.acdsee.com/iptc/1.0/">
+   <acdsee:keywords>
+    <rdf:Bag>
+     <rdf:li>First</rdf:li>
+     <rdf:li>
+      <rdf:Description>
+       <acdsee:note>Nested</acdsee:note>
+      </rdf:Description>
+     </rdf:li>
+     <rdf:li>Second</rdf:li>
+     <rdf:li>First</rdf:li>
+    </rdf:Bag>
+   </acdsee:keywords>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        xmp.acdsee_data().unwrap().keywords,
+        vec!["First".to_owned(), "Second".to_owned()]
+    );
+}
+
+#[test]
+fn test_set_xmp_base_url() {
+    let xpacket = XPacket::try_from(&include_bytes!("data/acdsee_data.xpacket")[..]).unwrap();
+    let xmp = XmpData::parse(xpacket.body).unwrap();
+
+    let rewritten = xmp
+        .write_events(vec![rules::set_xmp_base_url("file:///photos/cat.jpg")])
+        .unwrap();
+    let mut out = Vec::new();
+    write_events_to(&rewritten, &mut out, false).unwrap();
+    let out = String::from_utf8(out).unwrap();
+
+    assert!(out.contains("file:///photos/cat.jpg"));
+    assert!(XmpData::parse(out.as_bytes())
+        .unwrap()
+        .has_field(ns::XMP, "BaseURL"));
+}
+
+#[test]
+fn test_strip_xmp_mm_history() {
+    let xmp = XmpData::parse(
+        br#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description xmlns:xmpMM="http://ns.adobe.com/xap/1.0/mm/"
+    xmlns:stEvt="http://ns.adobe.com/xap/1.0/sType/ResourceEvent#">
+   <xmpMM:DocumentID>abc</xmpMM:DocumentID>
+   <xmpMM:History>
+    <rdf:Seq>
+     <rdf:li stEvt:action="saved" stEvt:softwareAgent="Adobe Photoshop Lightroom"/>
+     <rdf:li stEvt:action="saved" stEvt:softwareAgent="Adobe Photoshop Lightroom"/>
+    </rdf:Seq>
+   </xmpMM:History>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>"#,
+    )
+    .unwrap();
+
+    let rewritten = xmp
+        .write_events(vec![rules::strip_xmp_mm_history()])
+        .unwrap();
+    let mut out = Vec::new();
+    write_events_to(&rewritten, &mut out, false).unwrap();
+    let out = String::from_utf8(out).unwrap();
+
+    assert!(!out.contains("History"));
+    assert!(!out.contains("softwareAgent"));
+    assert!(out.contains("<xmpMM:DocumentID>abc</xmpMM:DocumentID>"));
+}
+
+#[test]
+fn test_strip_acdsee_after_convert() {
+    let xpacket = XPacket::try_from(&include_bytes!("data/acdsee_data.xpacket")[..]).unwrap();
+    let xmp = XmpData::parse(xpacket.body).unwrap();
+
+    let mapping = FieldMapping {
+        strip_acdsee_after_convert: true,
+        ..Default::default()
+    };
+
+    let rewritten = xmp
+        .write_events(xmp.acdsee_data().unwrap().to_ruleset_with_mapping(&mapping))
+        .unwrap();
+    let mut out = Vec::new();
+    write_events_to(&rewritten, &mut out, false).unwrap();
+    let out = String::from_utf8(out).unwrap();
+
+    assert!(out.contains("Vacances|Allos"));
+    assert!(!XmpData::parse(out.as_bytes())
+        .unwrap()
+        .has_namespace(ns::ACDSEE));
+}
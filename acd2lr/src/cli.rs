@@ -0,0 +1,166 @@
+//! Command-line subcommands running without the graphical interface
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use async_std::task::block_on;
+use color_eyre::eyre::{eyre, Result};
+use serde::Serialize;
+
+use acd2lr_core::{acdsee::AcdSeeData, container::Container};
+
+use crate::{
+    config::Config,
+    svc::{BackupMode, MetadataFile},
+    GlobalOpts,
+};
+
+/// Backup strategy, as specified on the command-line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Backup {
+    /// Backup files, keeping existing backups
+    Keep,
+    /// Backup files, overwriting existing backups
+    Overwrite,
+    /// Do not backup files
+    None,
+}
+
+impl From<Backup> for BackupMode {
+    fn from(backup: Backup) -> Self {
+        match backup {
+            Backup::Keep => Self::BackupKeep,
+            Backup::Overwrite => Self::BackupOverwrite,
+            Backup::None => Self::NoBackups,
+        }
+    }
+}
+
+/// Expand the given paths into the list of files to process
+///
+/// Files that cannot be opened are reported and skipped.
+fn collect_files(paths: Vec<PathBuf>) -> Vec<Arc<MetadataFile>> {
+    paths
+        .into_iter()
+        .flat_map(MetadataFile::from_path)
+        .filter_map(|result| match result {
+            Ok(file) => Some(file),
+            Err(error) => {
+                eprintln!("{}", error);
+                None
+            }
+        })
+        .collect()
+}
+
+fn report(files: &[MetadataFile]) -> Result<()> {
+    let mut errors = 0;
+
+    for file in files {
+        println!("{}\t{}", file.path().display(), file.state());
+
+        if file.state().into_error().is_some() {
+            errors += 1;
+        }
+    }
+
+    if errors > 0 {
+        Err(eyre!("{} file(s) could not be processed", errors))
+    } else {
+        Ok(())
+    }
+}
+
+pub fn check(opts: &GlobalOpts, paths: Vec<PathBuf>) -> Result<()> {
+    let _log_guard = opts.install_tracing(None);
+    let config = Config::load();
+
+    let files = block_on(async {
+        let mut result = Vec::new();
+        for file in collect_files(paths) {
+            result.push(file.check_rewrite(&config.field_mapping).await);
+        }
+        result
+    });
+
+    report(&files)
+}
+
+pub fn apply(
+    opts: &GlobalOpts,
+    paths: Vec<PathBuf>,
+    backup_mode: BackupMode,
+    output_dir: Option<&Path>,
+) -> Result<()> {
+    let _log_guard = opts.install_tracing(None);
+    let config = Config::load();
+    let mapping = &config.field_mapping;
+
+    let files = block_on(async {
+        let mut result = Vec::new();
+        for file in collect_files(paths) {
+            let file = file.check_rewrite(mapping).await;
+            result.push(file.apply(backup_mode, output_dir, mapping).await);
+        }
+        result
+    });
+
+    report(&files)
+}
+
+/// ACDSee data of a file, as written by [`write_export`]
+#[derive(Serialize)]
+pub struct ExportEntry {
+    pub path: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub acdsee: Option<AcdSeeData>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Write the exported `entries` to `out` as JSON
+pub fn write_export(entries: &[ExportEntry], out: &Path) -> Result<()> {
+    let writer = std::io::BufWriter::new(std::fs::File::create(out)?);
+    serde_json::to_writer_pretty(writer, entries)?;
+
+    Ok(())
+}
+
+async fn read_acdsee_data(path: &Path) -> Result<Option<AcdSeeData>> {
+    let file = async_std::fs::File::open(path).await?;
+    let mut container = Container::open(file).await.map_err(|(e, _)| e)?;
+
+    match container.read_xmp().await? {
+        Some((xmp, _)) => Ok(Some(xmp.acdsee_data()?)),
+        None => Ok(None),
+    }
+}
+
+pub fn export(opts: &GlobalOpts, paths: Vec<PathBuf>, out: &Path) -> Result<()> {
+    let _log_guard = opts.install_tracing(None);
+
+    let entries = block_on(async {
+        let mut result = Vec::new();
+        for file in collect_files(paths) {
+            let path = file.path().to_path_buf();
+
+            result.push(match read_acdsee_data(&path).await {
+                Ok(acdsee) => ExportEntry {
+                    path,
+                    acdsee,
+                    error: None,
+                },
+                Err(error) => ExportEntry {
+                    path,
+                    acdsee: None,
+                    error: Some(error.to_string()),
+                },
+            });
+        }
+        result
+    });
+
+    write_export(&entries, out)
+}
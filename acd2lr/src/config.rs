@@ -0,0 +1,117 @@
+//! User configuration, read from the `acd2lr` folder in the user configuration folder
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use acd2lr_core::acdsee::FieldMapping;
+
+use crate::svc::BackupMode;
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// XMP properties the ACDSee text fields are converted to
+    pub field_mapping: FieldMapping,
+    /// Backup mode selected when the application starts
+    pub backup_mode: BackupMode,
+    /// Log file used when none is given on the command line
+    pub log_file: Option<PathBuf>,
+}
+
+/// Configuration folder of acd2lr, if the user configuration folder is known
+fn config_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    }?;
+
+    Some(base.join("acd2lr"))
+}
+
+/// Write `value` as TOML to `path`, creating the configuration folder if needed
+fn save_toml(path: Option<PathBuf>, value: &impl Serialize) -> std::io::Result<()> {
+    let path = match path {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    // Going through a TOML value writes the plain values before the tables, as TOML requires
+    let contents = toml::Value::try_from(value)
+        .and_then(|value| toml::to_string_pretty(&value))
+        .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))?;
+
+    std::fs::write(&path, contents)
+}
+
+impl Config {
+    /// Path of the configuration file, if the user configuration folder is known
+    pub fn path() -> Option<PathBuf> {
+        Some(config_dir()?.join("config.toml"))
+    }
+
+    /// Load the configuration file, falling back to the defaults if it is missing or invalid
+    pub fn load() -> Self {
+        let path = match Self::path() {
+            Some(path) => path,
+            None => return Self::default(),
+        };
+
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => toml::from_str(&contents).unwrap_or_else(|error| {
+                tracing::warn!(path = %path.display(), %error, "invalid configuration file");
+                Self::default()
+            }),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(error) => {
+                tracing::warn!(path = %path.display(), %error, "cannot read configuration file");
+                Self::default()
+            }
+        }
+    }
+
+    /// Save this configuration, creating the configuration folder if needed
+    pub fn save(&self) -> std::io::Result<()> {
+        save_toml(Self::path(), self)
+    }
+}
+
+/// Size and position of the main window, saved when it is closed
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct WindowGeometry {
+    pub width: i32,
+    pub height: i32,
+    pub x: i32,
+    pub y: i32,
+}
+
+impl WindowGeometry {
+    /// Path of the window geometry file, if the user configuration folder is known
+    pub fn path() -> Option<PathBuf> {
+        Some(config_dir()?.join("window.toml"))
+    }
+
+    /// Load the last saved window geometry, if any
+    pub fn load() -> Option<Self> {
+        let path = Self::path()?;
+        let contents = std::fs::read_to_string(&path).ok()?;
+
+        toml::from_str(&contents)
+            .map_err(|error| {
+                tracing::warn!(path = %path.display(), %error, "invalid window geometry file");
+            })
+            .ok()
+    }
+
+    /// Save this window geometry, creating the configuration folder if needed
+    pub fn save(&self) -> std::io::Result<()> {
+        save_toml(Self::path(), self)
+    }
+}
@@ -3,13 +3,24 @@
 #[macro_use]
 extern crate tracing;
 
-use std::{cell::RefCell, path::PathBuf, rc::Rc};
+use std::{
+    cell::RefCell,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
 
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use color_eyre::eyre::Result;
-use structopt::StructOpt;
+use tracing::level_filters::LevelFilter;
+use tracing_appender::non_blocking::WorkerGuard;
 
 use gio::prelude::*;
-use gtk::{prelude::*, Application, ApplicationWindow, Builder};
+use gtk::{prelude::*, Application, ApplicationWindow, Builder, Inhibit};
+
+mod cli;
+
+mod config;
+use config::WindowGeometry;
 
 mod svc;
 use svc::*;
@@ -19,23 +30,141 @@ mod tr;
 mod ui;
 use ui::Ui;
 
-#[derive(Debug, StructOpt)]
+/// ACDSee to Lightroom metadata converter
+#[derive(Debug, Parser)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
 struct Opts {
-    extra_args: Vec<String>,
+    #[command(flatten)]
+    global: GlobalOpts,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Files or folders to open in the graphical interface
+    files: Vec<PathBuf>,
+}
+
+/// Options shared by all subcommands
+#[derive(Debug, Args)]
+pub struct GlobalOpts {
+    /// Append log messages to this file
+    #[arg(long, global = true)]
+    log_file: Option<PathBuf>,
+
+    /// Log all messages, including traces
+    #[arg(short, long, global = true, conflicts_with_all = ["quiet", "log_level"])]
+    verbose: bool,
+
+    /// Only log errors
+    #[arg(short, long, global = true, conflicts_with = "log_level")]
+    quiet: bool,
+
+    /// Minimum level of logged messages (overrides RUST_LOG)
+    #[arg(long, value_enum, global = true)]
+    log_level: Option<LogLevel>,
+}
+
+impl GlobalOpts {
+    fn level(&self) -> Option<LevelFilter> {
+        if self.verbose {
+            Some(LevelFilter::TRACE)
+        } else if self.quiet {
+            Some(LevelFilter::ERROR)
+        } else {
+            self.log_level.map(LevelFilter::from)
+        }
+    }
+
+    /// Install the tracing subscriber according to the logging options
+    ///
+    /// The log file of the configuration is used if none was given on the command line.
+    pub fn install_tracing(&self, tx: Option<MessageSender>) -> Option<WorkerGuard> {
+        let log_file = self
+            .log_file
+            .clone()
+            .or_else(|| config::Config::load().log_file);
+
+        crate::tr::install_with_file(tx, log_file.as_deref(), self.level())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Trace => Self::TRACE,
+            LogLevel::Debug => Self::DEBUG,
+            LogLevel::Info => Self::INFO,
+            LogLevel::Warn => Self::WARN,
+            LogLevel::Error => Self::ERROR,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Open the graphical interface (default)
+    Gui {
+        /// Files or folders to open
+        files: Vec<PathBuf>,
+    },
+    /// Print the conversion state of each file
+    Check {
+        /// Files or folders to check
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Convert each file and exit
+    Apply {
+        /// Files or folders to convert
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+
+        /// Backup strategy for the original files
+        #[arg(long, value_enum, default_value_t = cli::Backup::Keep)]
+        backup: cli::Backup,
+
+        /// Write converted files to this folder instead of overwriting the originals
+        #[arg(long)]
+        output_dir: Option<PathBuf>,
+    },
+    /// Export the ACDSee metadata of each file as JSON
+    Export {
+        /// Files or folders to export
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+
+        /// Path to the output JSON file
+        #[arg(long)]
+        out: PathBuf,
+    },
 }
 
 struct App {
-    opts: Opts,
+    opts: GlobalOpts,
+    files: Vec<PathBuf>,
 }
 
 impl App {
+    fn new(opts: GlobalOpts, files: Vec<PathBuf>) -> Self {
+        Self { opts, files }
+    }
+
     fn build_ui(&self, app: &Application) {
         // Setup tracing to the statusbar
         let (tx, rx) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
-        crate::tr::install(tx.clone());
+        let log_guard = RefCell::new(self.opts.install_tracing(Some(tx.clone())));
 
         // Initialize the backend service
-        let service = Rc::new(RefCell::new(Some(Service::new(tx).spawn())));
+        let service = Arc::new(Mutex::new(Some(Service::new(tx.clone()).spawn())));
 
         let glade_src = include_str!("ui/main.glade");
         let builder = Builder::from_string(glade_src);
@@ -43,25 +172,53 @@ impl App {
             .get_object("main_window")
             .expect("failed to load main window");
 
-        let ui = Ui::new(window.clone(), service.clone(), builder);
+        let ui = Ui::new(window.clone(), service.clone(), tx, builder);
         ui.build(rx);
 
         // Process input arguments
-        ui.add_files(
-            self.opts
-                .extra_args
-                .iter()
-                .map(|path| PathBuf::from(path))
-                .collect(),
-        );
+        ui.add_files(self.files.clone());
 
         // Set the window parent
         window.set_application(Some(app));
 
+        // Keyboard shortcuts for the window actions
+        app.set_accels_for_action("win.open", &["<Primary>o"]);
+        app.set_accels_for_action("win.open-folder", &["<Primary><Shift>o"]);
+        app.set_accels_for_action("win.apply", &["<Primary>Return"]);
+        app.set_accels_for_action("win.cancel", &["Escape"]);
+        app.set_accels_for_action("win.quit", &["<Primary>q"]);
+
+        // Restore the window geometry of the last session
+        if let Some(geometry) = WindowGeometry::load() {
+            window.set_default_size(geometry.width, geometry.height);
+            window.move_(geometry.x, geometry.y);
+        }
+
+        window.connect_delete_event(|window, _| {
+            let (width, height) = window.get_size();
+            let (x, y) = window.get_position();
+
+            let geometry = WindowGeometry {
+                width,
+                height,
+                x,
+                y,
+            };
+
+            if let Err(error) = geometry.save() {
+                warn!(%error, "cannot save window geometry");
+            }
+
+            Inhibit(false)
+        });
+
         // Destroy the service on application exit
         window.connect_destroy(move |_| {
             // Take out of the option to terminate the background service
-            service.borrow_mut().take();
+            service.lock().unwrap().take();
+
+            // Flush pending log messages
+            log_guard.borrow_mut().take();
         });
 
         info!(ui = true, "Démarrage de acd2lr terminé");
@@ -74,6 +231,9 @@ impl App {
             std::env::set_var("GTK_CSD", "0");
         }
 
+        // Recent files are registered under the application name
+        glib::set_application_name(ui::RECENT_APPLICATION_NAME);
+
         let application = Application::new(Some("io.github.vtavernier.acd2lr"), Default::default())
             .expect("failed to initialize GTK application");
 
@@ -85,15 +245,20 @@ impl App {
     }
 }
 
-impl From<Opts> for App {
-    fn from(opts: Opts) -> Self {
-        Self { opts }
-    }
-}
-
-#[paw::main]
-fn main(opts: Opts) -> Result<()> {
+fn main() -> Result<()> {
     color_eyre::install()?;
 
-    App::from(opts).run()
+    let opts = Opts::parse();
+
+    match opts.command {
+        None => App::new(opts.global, opts.files).run(),
+        Some(Command::Gui { files }) => App::new(opts.global, files).run(),
+        Some(Command::Check { paths }) => cli::check(&opts.global, paths),
+        Some(Command::Apply {
+            paths,
+            backup,
+            output_dir,
+        }) => cli::apply(&opts.global, paths, backup.into(), output_dir.as_deref()),
+        Some(Command::Export { paths, out }) => cli::export(&opts.global, paths, &out),
+    }
 }
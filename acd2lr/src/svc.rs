@@ -1,30 +1,104 @@
-use std::{convert::TryFrom, mem::ManuallyDrop, path::PathBuf};
+use std::{
+    convert::TryFrom,
+    mem::ManuallyDrop,
+    panic::AssertUnwindSafe,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
 
 use async_std::{
     channel,
     task::{block_on, JoinHandle},
 };
 use futures::{select, FutureExt};
+use notify::Watcher as _;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
 mod state;
 pub use state::*;
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+use crate::{
+    cli::{write_export, ExportEntry},
+    config::Config,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum BackupMode {
     BackupKeep,
     BackupOverwrite,
     NoBackups,
 }
 
+impl BackupMode {
+    /// All backup modes, in the order they are presented to the user
+    pub fn all() -> &'static [BackupMode] {
+        &[Self::BackupKeep, Self::BackupOverwrite, Self::NoBackups]
+    }
+
+    /// Position of this backup mode in [`BackupMode::all`]
+    pub fn index(&self) -> u32 {
+        Self::all()
+            .iter()
+            .position(|mode| mode == self)
+            .expect("backup mode missing from BackupMode::all") as u32
+    }
+
+    /// User-facing description of this backup mode
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::BackupKeep => "Sauvegarder (conserver la sauvegarde existante)",
+            Self::BackupOverwrite => "Sauvegarder (écraser la sauvegarde existante)",
+            Self::NoBackups => "Pas de sauvegarde",
+        }
+    }
+}
+
+impl std::fmt::Display for BackupMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+impl Default for BackupMode {
+    fn default() -> Self {
+        Self::BackupKeep
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("unknown backup mode: {0}")]
+pub struct UnknownBackupMode(pub u32);
+
 impl TryFrom<u32> for BackupMode {
-    type Error = ();
+    type Error = UnknownBackupMode;
 
     fn try_from(value: u32) -> Result<Self, Self::Error> {
-        match value {
-            0 => Ok(Self::BackupKeep),
-            1 => Ok(Self::BackupOverwrite),
-            2 => Ok(Self::NoBackups),
-            _ => Err(()),
+        Self::all()
+            .get(value as usize)
+            .copied()
+            .ok_or(UnknownBackupMode(value))
+    }
+}
+
+/// Files an apply request applies to
+#[derive(Debug, Clone)]
+pub enum ApplyFilter {
+    All,
+    Selected(Vec<PathBuf>),
+    WithState(FileStateKind),
+}
+
+impl ApplyFilter {
+    /// Returns true if `file` is selected by this filter
+    pub fn matches(&self, file: &MetadataFile) -> bool {
+        match self {
+            ApplyFilter::All => true,
+            ApplyFilter::Selected(paths) => paths.iter().any(|path| path == file.path()),
+            ApplyFilter::WithState(kind) => FileStateKind::from(file.state()) == *kind,
         }
     }
 }
@@ -33,23 +107,123 @@ impl TryFrom<u32> for BackupMode {
 #[derive(Debug)]
 pub enum Request {
     OpenPaths(Vec<PathBuf>),
-    Apply(BackupMode),
+    Apply {
+        backup_mode: BackupMode,
+        filter: ApplyFilter,
+        /// Check the files which are not ready again, instead of skipping them
+        recheck: bool,
+    },
+    Cancel,
+    Remove(Vec<PathBuf>),
+    RefreshAll,
+    WatchDir(PathBuf),
+    Unwatch,
+    /// Use the given configuration for the next conversions
+    UpdateConfig(Config),
+    /// Write the ACDSee data of the listed files to the given JSON file
+    Export(PathBuf),
 }
 
 pub type RequestSender = channel::Sender<Request>;
 pub type RequestReceiver = channel::Receiver<Request>;
 
+/// Kind of background work a progress update refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressKind {
+    Check,
+    Apply,
+}
+
+impl ProgressKind {
+    /// User-facing description of this kind of work
+    pub fn label(&self) -> &'static str {
+        match self {
+            ProgressKind::Check => "Vérification des fichiers",
+            ProgressKind::Apply => "Application des conversions",
+        }
+    }
+}
+
 /// A message from the backend to the UI
 #[derive(Debug)]
 pub enum Message {
     Status(String),
     AddPathsComplete(AddFilesResult),
     FileStateUpdate(Vec<Event>),
-    ProgressUpdate { current: usize, total: usize },
+    ProgressUpdate {
+        current: usize,
+        total: usize,
+        kind: ProgressKind,
+        /// Number of tasks waiting to be run
+        pending: usize,
+        /// File name of the file being processed
+        current_file: Option<String>,
+    },
+    /// All the background tasks of a batch are done
+    BatchComplete {
+        kind: ProgressKind,
+        applied: usize,
+        already_done: usize,
+        errors: usize,
+    },
+    Cancelled,
+    FatalError(String),
 }
 
 pub type MessageSender = glib::Sender<Message>;
 
+/// Watches folders for new or modified files
+pub struct Watcher {
+    inner: notify::RecommendedWatcher,
+    rx: channel::Receiver<PathBuf>,
+}
+
+impl Watcher {
+    pub fn new() -> notify::Result<Self> {
+        let (tx, rx) = channel::unbounded();
+
+        let inner =
+            notify::recommended_watcher(
+                move |result: notify::Result<notify::Event>| match result {
+                    Ok(event) => {
+                        if matches!(
+                            event.kind,
+                            notify::EventKind::Create(_) | notify::EventKind::Modify(_)
+                        ) {
+                            for path in event.paths {
+                                tx.try_send(path).ok();
+                            }
+                        }
+                    }
+                    Err(error) => {
+                        tracing::warn!(%error, "watch error");
+                    }
+                },
+            )?;
+
+        Ok(Self { inner, rx })
+    }
+
+    pub fn watch(&mut self, dir: &Path) -> notify::Result<()> {
+        self.inner.watch(dir, notify::RecursiveMode::Recursive)
+    }
+
+    /// Wait for the next supported file to be created or modified
+    pub async fn next_path(&self) -> Option<PathBuf> {
+        while let Ok(path) = self.rx.recv().await {
+            if path.is_file() && is_supported(&path) {
+                return Some(path);
+            }
+        }
+
+        None
+    }
+}
+
+/// Backend service processing the requests of the UI
+///
+/// Clones report to the same UI channel.
+#[derive(Clone)]
 pub struct Service {
     ui: MessageSender,
 }
@@ -59,12 +233,28 @@ impl Service {
         Self { ui }
     }
 
+    /// Send a message to the UI
+    ///
+    /// # Returns
+    ///
+    /// `true` if the message was sent, `false` if the UI is gone.
+    fn try_send(&self, msg: Message) -> bool {
+        match self.ui.send(msg) {
+            Ok(_) => true,
+            Err(error) => {
+                tracing::warn!(%error, "failed to send message to the UI");
+                false
+            }
+        }
+    }
+
     async fn run(self, rx: RequestReceiver) {
         info!("started backend service");
 
         // Initialize service state
-        let mut state = State::new();
-        let mut current_progress_total: Option<usize> = None;
+        let mut state = State::new(Config::load().field_mapping);
+        let mut current_progress_kind = ProgressKind::Check;
+        let mut watcher: Option<Watcher> = None;
 
         loop {
             // Listen for child tasks and channels
@@ -76,18 +266,86 @@ impl Service {
                                 let (result, bg_tasks) = state.add_files(paths);
 
                                 if bg_tasks != 0 {
-                                    current_progress_total = Some(bg_tasks);
+                                    current_progress_kind = ProgressKind::Check;
                                 }
 
-                                self.ui
-                                    .send(Message::AddPathsComplete(result))
-                                    .unwrap();
+                                self.try_send(Message::AddPathsComplete(result));
                             },
-                            Request::Apply(backup_mode) => {
-                                let bg_tasks = state.start_apply(backup_mode);
+                            Request::Apply { backup_mode, filter, recheck } => {
+                                let bg_tasks = state.start_apply(backup_mode, &filter, recheck);
 
                                 if bg_tasks != 0 {
-                                    current_progress_total = Some(bg_tasks);
+                                    current_progress_kind = ProgressKind::Apply;
+                                }
+                            },
+                            Request::Cancel => {
+                                let cancelled = state.cancel();
+
+                                tracing::info!(%cancelled, "cancelled background tasks");
+                            },
+                            Request::Remove(paths) => {
+                                state.remove(&paths);
+                            },
+                            Request::RefreshAll => {
+                                let bg_tasks = state.refresh_all();
+
+                                if bg_tasks != 0 {
+                                    current_progress_kind = ProgressKind::Check;
+                                }
+                            },
+                            Request::WatchDir(dir) => {
+                                if watcher.is_none() {
+                                    match Watcher::new() {
+                                        Ok(new_watcher) => {
+                                            watcher = Some(new_watcher);
+                                        },
+                                        Err(error) => {
+                                            tracing::warn!(ui = true, "Impossible de surveiller les dossiers : {}", error);
+                                        }
+                                    }
+                                }
+
+                                if let Some(watcher) = &mut watcher {
+                                    match watcher.watch(&dir) {
+                                        Ok(_) => {
+                                            tracing::info!(ui = true, "Surveillance de {}", dir.display());
+                                        },
+                                        Err(error) => {
+                                            tracing::warn!(ui = true, "Impossible de surveiller {} : {}", dir.display(), error);
+                                        }
+                                    }
+                                }
+                            },
+                            Request::Unwatch => {
+                                if watcher.take().is_some() {
+                                    tracing::info!(ui = true, "Surveillance arrêtée");
+                                }
+                            },
+                            Request::UpdateConfig(config) => {
+                                let bg_tasks = state.set_mapping(config.field_mapping);
+
+                                if bg_tasks != 0 {
+                                    current_progress_kind = ProgressKind::Check;
+                                }
+                            },
+                            Request::Export(out) => {
+                                let entries: Vec<_> = state
+                                    .export_acdsee_data()
+                                    .into_iter()
+                                    .map(|(path, acdsee)| ExportEntry {
+                                        path: (*path).clone(),
+                                        acdsee,
+                                        error: None,
+                                    })
+                                    .collect();
+
+                                match write_export(&entries, &out) {
+                                    Ok(()) => {
+                                        tracing::info!(ui = true, "Métadonnées exportées dans {}", out.display());
+                                    },
+                                    Err(error) => {
+                                        tracing::warn!(ui = true, "Impossible d'exporter les métadonnées : {}", error);
+                                    }
                                 }
                             }
                         },
@@ -97,69 +355,137 @@ impl Service {
                         }
                     }
                 },
+                path = async {
+                    match &watcher {
+                        Some(watcher) => watcher.next_path().await,
+                        None => futures::future::pending().await,
+                    }
+                }.fuse() => {
+                    if let Some(path) = path {
+                        let bg_tasks = state.add_watched(path);
+
+                        if bg_tasks != 0 {
+                            current_progress_kind = ProgressKind::Check;
+                        }
+                    }
+                },
                 progress = state.poll_bg().fuse() => {
                     // No further processing required
                     match progress {
                         BackgroundProgress::Left(left) => {
-                            let total = current_progress_total.unwrap_or_else(|| {
-                                tracing::warn!("no total progress");
-                                left + 1
-                            });
+                            // Tasks may be queued while the batch is running, so the total
+                            // covers all the tasks queued since the batch started
+                            let total = state.total_tasks_ever_queued().max(left);
 
-                            self.ui.send(Message::ProgressUpdate {
+                            self.try_send(Message::ProgressUpdate {
                                 current: total - left,
                                 total,
-                            }).unwrap();
+                                kind: current_progress_kind,
+                                pending: state.pending_count(),
+                                current_file: state.next_file_name(),
+                            });
                         },
                         BackgroundProgress::Complete => {
-                            match current_progress_total.take() {
-                                Some(total) => {
-                                    self.ui.send(Message::ProgressUpdate {
-                                        current: total,
-                                        total,
-                                    }).unwrap();
-                                },
-                                None => {
-                                    self.ui.send(Message::ProgressUpdate {
-                                        current: 1,
-                                        total: 1
-                                    }).unwrap();
-                                }
-                            }
+                            let stats = state.take_batch_stats();
+                            self.try_send(Message::BatchComplete {
+                                kind: current_progress_kind,
+                                applied: stats.applied,
+                                already_done: stats.already_done,
+                                errors: stats.errors,
+                            });
+                        },
+                        BackgroundProgress::Cancelled => {
+                            current_progress_kind = ProgressKind::Check;
+                            self.try_send(Message::Cancelled);
                         }
                     }
                 }
             }
 
             let events = state.drain_events();
-            if !events.is_empty() {
-                self.ui.send(Message::FileStateUpdate(events)).unwrap();
+            if !events.is_empty() && !self.try_send(Message::FileStateUpdate(events)) {
+                // The UI is gone, nobody is listening for results anymore
+                break;
             }
         }
     }
 
+    fn panic_message(error: &(dyn std::any::Any + Send)) -> String {
+        if let Some(message) = error.downcast_ref::<&str>() {
+            (*message).to_owned()
+        } else if let Some(message) = error.downcast_ref::<String>() {
+            message.clone()
+        } else {
+            "unknown error".to_owned()
+        }
+    }
+
     pub fn spawn(self) -> ServiceHandle {
         // Create the request channel
         let (tx, rx) = channel::unbounded();
+        // Keep a sender to report panics to the UI
+        let ui = self.ui.clone();
+        let failure_reported = Arc::new(AtomicBool::new(false));
         // Create the thread handle
-        let join_handle = async_std::task::spawn(self.run(rx));
+        let join_handle = async_std::task::spawn({
+            let failure_reported = failure_reported.clone();
+
+            async move {
+                if let Err(error) = AssertUnwindSafe(self.run(rx)).catch_unwind().await {
+                    let message = Self::panic_message(&*error);
+                    tracing::error!(%message, "backend service panicked");
+
+                    // Set before sending, so the UI never sees the message without the flag
+                    failure_reported.store(true, Ordering::SeqCst);
+                    ui.send(Message::FatalError(message)).ok();
+                }
+            }
+        });
 
         ServiceHandle {
             tx: ManuallyDrop::new(tx),
             join_handle: ManuallyDrop::new(join_handle),
+            failure_reported,
         }
     }
 }
 
+/// Handle to a running service, shared by the windows using it
+pub type SharedServiceHandle = Arc<Mutex<Option<ServiceHandle>>>;
+
+#[derive(Debug, Error)]
+#[error("the backend service is not running")]
+pub struct ServiceStopped;
+
 pub struct ServiceHandle {
     tx: ManuallyDrop<RequestSender>,
     join_handle: ManuallyDrop<JoinHandle<()>>,
+    failure_reported: Arc<AtomicBool>,
 }
 
 impl ServiceHandle {
-    pub fn send_request(&self, request: Request) {
+    pub fn send_request(&self, request: Request) -> Result<(), ServiceStopped> {
         tracing::debug!(request = ?request, "sending");
-        block_on(self.tx.send(request)).expect("failed sending request")
+        block_on(self.tx.send(request)).map_err(|_| ServiceStopped)
+    }
+
+    /// Returns `false` if the service task stopped, after a panic for example
+    pub fn is_alive(&self) -> bool {
+        // The request receiver is dropped when the service task stops
+        !self.tx.is_closed()
+    }
+
+    /// Returns `true` if the service stopped after sending [`Message::FatalError`]
+    pub fn failure_reported(&self) -> bool {
+        self.failure_reported.load(Ordering::SeqCst)
+    }
+
+    /// Replace the service with a new one, reporting to `ui`
+    ///
+    /// The state of the previous service is lost.
+    pub fn restart(&mut self, ui: MessageSender) {
+        // Dropping the previous handle joins its task
+        drop(std::mem::replace(self, Service::new(ui).spawn()));
     }
 }
 
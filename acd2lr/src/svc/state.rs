@@ -1,6 +1,9 @@
-use std::{collections::VecDeque, convert::TryFrom, path::PathBuf, sync::Arc};
+use std::{collections::VecDeque, path::PathBuf, sync::Arc};
 
-use super::BackupMode;
+use acd2lr_core::acdsee::{AcdSeeData, FieldMapping};
+use tracing::Instrument;
+
+use super::{ApplyFilter, BackupMode};
 
 mod file_state;
 pub use file_state::*;
@@ -18,13 +21,25 @@ pub enum Event {
         start: usize,
         files: Vec<Arc<MetadataFile>>,
     },
+    Removed {
+        index: usize,
+    },
 }
 
+// Files and their states are shared with the UI thread
+const _: fn() = || {
+    fn check<T: Send + Sync>() {}
+    check::<MetadataFile>();
+    check::<FileState>();
+    check::<Event>();
+};
+
 #[derive(Debug)]
 enum BackgroundTask {
     TryRewrite {
         index: usize,
         file: Arc<MetadataFile>,
+        force: bool,
     },
     Apply {
         index: usize,
@@ -62,11 +77,42 @@ macro_rules! update_file {
 }
 
 impl BackgroundTask {
-    async fn try_rewrite_inner(file: Arc<MetadataFile>, state_file: &mut Arc<MetadataFile>) {
+    fn index(&self) -> usize {
+        match self {
+            BackgroundTask::TryRewrite { index, .. } | BackgroundTask::Apply { index, .. } => {
+                *index
+            }
+        }
+    }
+
+    /// Name of this kind of task, for tracing
+    fn kind_name(&self) -> &'static str {
+        match self {
+            BackgroundTask::TryRewrite { .. } => "try_rewrite",
+            BackgroundTask::Apply { .. } => "apply",
+        }
+    }
+
+    fn index_mut(&mut self) -> &mut usize {
+        match self {
+            BackgroundTask::TryRewrite { index, .. } | BackgroundTask::Apply { index, .. } => index,
+        }
+    }
+
+    async fn try_rewrite_inner(
+        file: Arc<MetadataFile>,
+        force: bool,
+        mapping: Arc<FieldMapping>,
+        state_file: &mut Arc<MetadataFile>,
+    ) {
         // We are working on the right file
         // Try reading the metadata
-        let new_file = file.check_rewrite().await;
-        tracing::info!(new_state = ?FileStateKind::from(new_file.state()), "checked rewrite");
+        let new_file = if force {
+            file.force_check_rewrite(&mapping).await
+        } else {
+            file.check_rewrite(&mapping).await
+        };
+        tracing::info!(new_state = %FileStateKind::from(new_file.state()), "checked rewrite");
 
         // Update the slot
         *state_file = Arc::new(new_file);
@@ -75,20 +121,22 @@ impl BackgroundTask {
     async fn apply_inner(
         file: Arc<MetadataFile>,
         backup_mode: BackupMode,
+        mapping: Arc<FieldMapping>,
         state_file: &mut Arc<MetadataFile>,
     ) {
         // We are working on the right file
         // Try reading the metadata
-        let new_file = file.apply(backup_mode).await;
-        tracing::info!(new_state = ?FileStateKind::from(new_file.state()), "applied rewrite");
+        let new_file = file.apply(backup_mode, None, &mapping).await;
+        tracing::info!(new_state = %FileStateKind::from(new_file.state()), "applied rewrite");
 
         // Update the slot
         *state_file = Arc::new(new_file);
     }
 
     #[tracing::instrument(skip(state))]
-    async fn try_rewrite(index: usize, file: Arc<MetadataFile>, state: &mut State) {
-        update_file!(index, file, state, Self::try_rewrite_inner)
+    async fn try_rewrite(index: usize, file: Arc<MetadataFile>, force: bool, state: &mut State) {
+        let mapping = state.mapping.clone();
+        update_file!(index, file, state, Self::try_rewrite_inner, force, mapping)
     }
 
     #[tracing::instrument(skip(state))]
@@ -98,13 +146,18 @@ impl BackgroundTask {
         backup_mode: BackupMode,
         state: &mut State,
     ) {
-        update_file!(index, file, state, Self::apply_inner, backup_mode)
+        let mapping = state.mapping.clone();
+        update_file!(index, file, state, Self::apply_inner, backup_mode, mapping);
+
+        if let Some(file) = state.files.get(index) {
+            state.batch_stats.record(file.state());
+        }
     }
 
     async fn run(self, state: &mut State) {
         match self {
-            BackgroundTask::TryRewrite { index, file } => {
-                Self::try_rewrite(index, file, state).await;
+            BackgroundTask::TryRewrite { index, file, force } => {
+                Self::try_rewrite(index, file, force, state).await;
             }
             BackgroundTask::Apply {
                 index,
@@ -117,11 +170,36 @@ impl BackgroundTask {
     }
 }
 
+/// Outcome of the apply tasks of a batch
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchStats {
+    pub applied: usize,
+    pub already_done: usize,
+    pub errors: usize,
+}
+
+impl BatchStats {
+    fn record(&mut self, state: &FileState) {
+        match state {
+            FileState::Complete(_, _) => self.applied += 1,
+            FileState::NoAcdData | FileState::AlreadyConverted => self.already_done += 1,
+            other if other.into_error().is_some() => self.errors += 1,
+            _ => {}
+        }
+    }
+}
+
 #[derive(Default, Debug)]
 pub struct State {
     files: Vec<Arc<MetadataFile>>,
     file_events: Vec<Event>,
     pending_tasks: VecDeque<BackgroundTask>,
+    batch_stats: BatchStats,
+    /// Number of tasks queued since the current batch started
+    total_tasks_ever_queued: usize,
+    mapping: Arc<FieldMapping>,
+    /// Set when the pending tasks were cancelled, until reported by [`State::poll_bg`]
+    cancelled: bool,
 }
 
 pub type AddFilesResult = Vec<Result<Arc<MetadataFile>, FileError>>;
@@ -130,6 +208,8 @@ pub type AddFilesResult = Vec<Result<Arc<MetadataFile>, FileError>>;
 pub enum BackgroundProgress {
     Left(usize),
     Complete,
+    /// The pending tasks were cancelled
+    Cancelled,
 }
 
 impl From<usize> for BackgroundProgress {
@@ -143,20 +223,49 @@ impl From<usize> for BackgroundProgress {
 }
 
 impl State {
-    pub fn new() -> Self {
-        Self::default()
+    pub fn new(mapping: FieldMapping) -> Self {
+        Self {
+            mapping: Arc::new(mapping),
+            ..Default::default()
+        }
+    }
+
+    /// Use `mapping` for the background tasks run from now on
+    ///
+    /// The packets of the files ready for conversion were prepared with the previous mapping, so
+    /// these files are checked again if the mapping changed.
+    ///
+    /// # Returns
+    ///
+    /// The pending number of background tasks.
+    pub fn set_mapping(&mut self, mapping: FieldMapping) -> usize {
+        if *self.mapping == mapping {
+            return self.pending_tasks.len();
+        }
+
+        self.mapping = Arc::new(mapping);
+
+        for (index, file) in self.files.iter().enumerate() {
+            if matches!(
+                file.state(),
+                FileState::Ready(_, _) | FileState::PartialAcdData(_, _)
+            ) {
+                self.pending_tasks.push_back(BackgroundTask::TryRewrite {
+                    index,
+                    file: file.clone(),
+                    force: true,
+                });
+                self.total_tasks_ever_queued += 1;
+            }
+        }
+
+        self.pending_tasks.len()
     }
 
     pub fn add_files(&mut self, paths: Vec<PathBuf>) -> (AddFilesResult, usize) {
         let results: Vec<_> = paths
             .into_iter()
-            .flat_map(|path| {
-                if path.is_dir() {
-                    MetadataFile::from_dir(&path)
-                } else {
-                    vec![MetadataFile::try_from(path).map(Arc::new)]
-                }
-            })
+            .flat_map(MetadataFile::from_path)
             .collect();
 
         // Range start for added events
@@ -172,7 +281,9 @@ impl State {
                 self.pending_tasks.push_back(BackgroundTask::TryRewrite {
                     index: self.files.len() - 1,
                     file: file.clone(),
+                    force: false,
                 });
+                self.total_tasks_ever_queued += 1;
             }
         }
 
@@ -187,12 +298,88 @@ impl State {
         (results, self.pending_tasks.len())
     }
 
+    /// Add or re-check a file reported by the folder watcher
+    ///
     /// # Returns
     ///
     /// The pending number of background tasks.
-    pub fn start_apply(&mut self, backup_mode: BackupMode) -> usize {
+    pub fn add_watched(&mut self, path: PathBuf) -> usize {
+        // Skip files which are already waiting for a check
+        if self.pending_tasks.iter().any(
+            |task| matches!(task, BackgroundTask::TryRewrite { file, .. } if file.path() == path),
+        ) {
+            return self.pending_tasks.len();
+        }
+
+        if let Some(index) = self.files.iter().position(|file| file.path() == path) {
+            // Our own writes are reported too, skip them
+            if self.files[index].unchanged_since_check() {
+                return self.pending_tasks.len();
+            }
+
+            // Known file, check it again
+            self.pending_tasks.push_back(BackgroundTask::TryRewrite {
+                index,
+                file: self.files[index].clone(),
+                force: false,
+            });
+            self.total_tasks_ever_queued += 1;
+
+            self.pending_tasks.len()
+        } else {
+            // New file, add it to the list
+            self.add_files(vec![path]).1
+        }
+    }
+
+    /// Check all files again, including the ones which were not modified since their last check
+    ///
+    /// # Returns
+    ///
+    /// The pending number of background tasks.
+    pub fn refresh_all(&mut self) -> usize {
         for (index, file) in self.files.iter().enumerate() {
-            if matches!(file.state(), FileState::Ready(_)) {
+            self.pending_tasks.push_back(BackgroundTask::TryRewrite {
+                index,
+                file: file.clone(),
+                force: true,
+            });
+            self.total_tasks_ever_queued += 1;
+        }
+
+        self.pending_tasks.len()
+    }
+
+    /// Queue the files matching `filter` for apply
+    ///
+    /// Only ready files are queued, unless `recheck` is set: every file which is not complete
+    /// yet is then queued, and checked again before being applied.
+    ///
+    /// # Returns
+    ///
+    /// The pending number of background tasks.
+    pub fn start_apply(
+        &mut self,
+        backup_mode: BackupMode,
+        filter: &ApplyFilter,
+        recheck: bool,
+    ) -> usize {
+        // The progress of the apply batch starts from the tasks which are still pending
+        self.total_tasks_ever_queued = self.pending_tasks.len();
+
+        for (index, file) in self.files.iter().enumerate() {
+            if !filter.matches(file) {
+                continue;
+            }
+
+            // Files explicitly selected by their state are checked again when applying
+            if matches!(filter, ApplyFilter::WithState(_))
+                || (recheck && !matches!(file.state(), FileState::Complete(_, _)))
+                || matches!(
+                    file.state(),
+                    FileState::Ready(_, _) | FileState::PartialAcdData(_, _)
+                )
+            {
                 // The file is ready to be rewritten
                 tracing::debug!(path = %file.path().display(), "queuing file for apply");
                 self.pending_tasks.push_back(BackgroundTask::Apply {
@@ -200,18 +387,66 @@ impl State {
                     file: file.clone(),
                     backup_mode,
                 });
+                self.total_tasks_ever_queued += 1;
             }
         }
 
         self.pending_tasks.len()
     }
 
+    /// Remove the given files from the list, along with their pending tasks
+    pub fn remove(&mut self, paths: &[PathBuf]) {
+        // Go backwards, so the indices of the files left to check are not shifted
+        for index in (0..self.files.len()).rev() {
+            if !paths.iter().any(|path| path == self.files[index].path()) {
+                continue;
+            }
+
+            self.files.remove(index);
+            self.file_events.push(Event::Removed { index });
+
+            // Drop the tasks for this file, and shift the ones for the following files
+            self.pending_tasks.retain(|task| task.index() != index);
+            for task in &mut self.pending_tasks {
+                let task_index = task.index_mut();
+                if *task_index > index {
+                    *task_index -= 1;
+                }
+            }
+        }
+    }
+
+    /// Cancel all pending background tasks
+    ///
+    /// The next call to [`State::poll_bg`] returns [`BackgroundProgress::Cancelled`].
+    ///
+    /// # Returns
+    ///
+    /// The number of background tasks that were cancelled.
+    pub fn cancel(&mut self) -> usize {
+        let cancelled = self.pending_tasks.len();
+        self.pending_tasks.clear();
+        self.batch_stats = BatchStats::default();
+        self.total_tasks_ever_queued = 0;
+        self.cancelled = true;
+        cancelled
+    }
+
     pub async fn poll_bg(&mut self) -> BackgroundProgress {
-        if let Some(task) = self.pending_tasks.pop_front() {
+        if std::mem::take(&mut self.cancelled) {
+            // Report the cancellation before running anything else
+            BackgroundProgress::Cancelled
+        } else if let Some(task) = self.pending_tasks.pop_front() {
             // Something to do
-            task.run(self).await;
+            let span = tracing::info_span!("background_task", kind = task.kind_name());
+            task.run(self).instrument(span).await;
 
-            BackgroundProgress::from(self.pending_tasks.len())
+            let progress = BackgroundProgress::from(self.pending_tasks.len());
+            if let BackgroundProgress::Complete = progress {
+                self.total_tasks_ever_queued = 0;
+            }
+
+            progress
         } else {
             // Nothing to do
             futures::future::pending::<()>().await;
@@ -220,7 +455,208 @@ impl State {
         }
     }
 
+    /// File name of the file the next background task will process
+    pub fn next_file_name(&self) -> Option<String> {
+        let task = self.pending_tasks.front()?;
+        let name = self.files.get(task.index())?.path().file_name()?;
+
+        Some(name.to_string_lossy().into_owned())
+    }
+
+    /// Number of background tasks waiting to be run
+    pub fn pending_count(&self) -> usize {
+        self.pending_tasks.len()
+    }
+
+    /// Number of tasks queued since the current batch started, including completed ones
+    pub fn total_tasks_ever_queued(&self) -> usize {
+        self.total_tasks_ever_queued
+    }
+
+    /// ACDSee data of every file, as read by the last check
+    ///
+    /// The data is `None` for the files which are neither ready for conversion nor converted.
+    pub fn export_acdsee_data(&self) -> Vec<(Arc<PathBuf>, Option<AcdSeeData>)> {
+        self.files
+            .iter()
+            .map(|file| {
+                (
+                    file.shared_path().clone(),
+                    file.state().acdsee_data().map(|acd| (**acd).clone()),
+                )
+            })
+            .collect()
+    }
+
+    /// Take the outcome of the apply tasks run since the last call
+    pub fn take_batch_stats(&mut self) -> BatchStats {
+        std::mem::take(&mut self.batch_stats)
+    }
+
     pub fn drain_events(&mut self) -> Vec<Event> {
         self.file_events.drain(..).collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run the pending background tasks
+    async fn run_pending(state: &mut State) {
+        while state.pending_count() > 0 {
+            state.poll_bg().await;
+        }
+    }
+
+    fn ready_packet(state: &State) -> String {
+        match state.files[0].state() {
+            FileState::Ready(packet, _) => String::from_utf8_lossy(packet).into_owned(),
+            other => panic!("unexpected state: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_set_mapping_rechecks_ready_files() {
+        let path = std::env::temp_dir().join("acd2lr_test_set_mapping.nef");
+        std::fs::copy(
+            concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/../acd2lr-core/tests/data/acdsee_data.nef"
+            ),
+            &path,
+        )
+        .expect("failed to copy test file");
+
+        async_std::task::block_on(async {
+            let mut state = State::new(FieldMapping::default());
+            state.add_files(vec![path.clone()]);
+            run_pending(&mut state).await;
+
+            assert!(ready_packet(&state).contains("Vacances|Allos"));
+
+            // Setting the same mapping does not check the files again
+            assert_eq!(state.set_mapping(FieldMapping::default()), 0);
+
+            let queued = state.set_mapping(FieldMapping {
+                tag_separator: '/',
+                ..Default::default()
+            });
+            assert_eq!(queued, 1);
+            run_pending(&mut state).await;
+
+            assert!(ready_packet(&state).contains("Vacances/Allos"));
+        });
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_preview_matches_ready_packet() {
+        let path = std::env::temp_dir().join("acd2lr_test_preview.nef");
+        std::fs::copy(
+            concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/../acd2lr-core/tests/data/acdsee_data.nef"
+            ),
+            &path,
+        )
+        .expect("failed to copy test file");
+
+        async_std::task::block_on(async {
+            let mapping = FieldMapping {
+                strip_history_before_convert: true,
+                ..Default::default()
+            };
+            let mut state = State::new(mapping.clone());
+            state.add_files(vec![path.clone()]);
+            run_pending(&mut state).await;
+
+            let preview = state.files[0]
+                .preview_converted_xmp(&mapping)
+                .await
+                .expect("failed to preview the conversion");
+            assert_eq!(preview, ready_packet(&state));
+        });
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_export_acdsee_data_of_converted_files() {
+        let path = std::env::temp_dir().join("acd2lr_test_export_converted.nef");
+        std::fs::copy(
+            concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/../acd2lr-core/tests/data/acdsee_data.nef"
+            ),
+            &path,
+        )
+        .expect("failed to copy test file");
+
+        async_std::task::block_on(async {
+            let mut state = State::new(FieldMapping::default());
+            state.add_files(vec![path.clone()]);
+            run_pending(&mut state).await;
+
+            let before = state.export_acdsee_data();
+            assert!(before[0].1.is_some());
+
+            state.start_apply(BackupMode::NoBackups, &ApplyFilter::All, false);
+            run_pending(&mut state).await;
+
+            assert!(matches!(state.files[0].state(), FileState::Complete(_, _)));
+            assert_eq!(state.export_acdsee_data()[0].1, before[0].1);
+        });
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_watched_apply_stays_complete() {
+        let path = std::env::temp_dir().join("acd2lr_test_watched_apply.nef");
+        std::fs::copy(
+            concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/../acd2lr-core/tests/data/acdsee_data.nef"
+            ),
+            &path,
+        )
+        .expect("failed to copy test file");
+
+        async_std::task::block_on(async {
+            let mut state = State::new(FieldMapping::default());
+            state.add_files(vec![path.clone()]);
+            run_pending(&mut state).await;
+
+            state.start_apply(BackupMode::NoBackups, &ApplyFilter::All, false);
+            run_pending(&mut state).await;
+            assert!(matches!(state.files[0].state(), FileState::Complete(_, _)));
+
+            // The watcher reports the write of the conversion
+            assert_eq!(state.add_watched(path.clone()), 0);
+            run_pending(&mut state).await;
+
+            assert!(matches!(state.files[0].state(), FileState::Complete(_, _)));
+        });
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_file_errors_hold_the_path() {
+        let path = std::env::temp_dir().join("acd2lr_test_missing.nef");
+        std::fs::remove_file(&path).ok();
+
+        async_std::task::block_on(async {
+            let mut state = State::new(FieldMapping::default());
+            state.add_files(vec![path.clone()]);
+            run_pending(&mut state).await;
+
+            assert!(matches!(state.files[0].state(), FileState::IoError(_)));
+
+            let error = state.files[0].error().expect("missing error");
+            assert!(error.to_string().contains(&path.display().to_string()));
+        });
+    }
+}
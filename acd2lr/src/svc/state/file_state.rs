@@ -1,28 +1,109 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use strum_macros::{AsRefStr, EnumDiscriminants};
 
 use acd2lr_core::{
-    acdsee::AcdSeeError,
+    acdsee::{AcdSeeData, AcdSeeError, AcdSeeWarning},
     container::{ContainerError, ContainerRewriteError, ContainerWriteError},
     xmp::WriteError,
 };
 
+use super::PathContext;
+
 #[derive(Debug, Clone, EnumDiscriminants)]
 #[strum_discriminants(name(FileStateKind), derive(AsRefStr))]
 pub enum FileState {
     Init,
-    IoError(Arc<std::io::Error>),
+    IoError(Arc<PathContext<std::io::Error>>),
     NoXmpData,
     NoAcdData,
-    ContainerError(Arc<ContainerError>),
-    XmpRewriteError(Arc<WriteError>),
-    InvalidAcdseeData(Arc<AcdSeeError>),
-    Ready(Arc<Vec<u8>>),
-    RewriteError(Arc<ContainerRewriteError>),
-    Complete,
-    ApplyError(Arc<ContainerWriteError>),
-    BackupError(Arc<std::io::Error>),
+    /// The file was already converted, and has no ACDSee data left
+    AlreadyConverted,
+    ContainerError(Arc<PathContext<ContainerError>>),
+    XmpRewriteError(Arc<PathContext<WriteError>>),
+    InvalidAcdseeData(Arc<PathContext<AcdSeeError>>),
+    /// Some fields are invalid, but the other ones can still be converted
+    PartialAcdData(Arc<AcdSeeWarning>, Arc<AcdSeeData>),
+    /// The rewritten packet is ready, along with the ACDSee data it was converted from
+    Ready(Arc<Vec<u8>>, Arc<AcdSeeData>),
+    RewriteError(Arc<PathContext<ContainerRewriteError>>),
+    /// The file was converted, from the ACDSee data it held before
+    Complete(Arc<ApplyStats>, Arc<AcdSeeData>),
+    ApplyError(Arc<PathContext<ContainerWriteError>>),
+    BackupError(Arc<PathContext<std::io::Error>>),
+}
+
+/// Measurements of a successful conversion
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ApplyStats {
+    /// Time spent preparing the rewritten packet
+    pub check_duration: Duration,
+    /// Time spent writing the packet
+    pub write_duration: Duration,
+    /// Size of the XMP data before the conversion, excluding padding, in bytes
+    pub original_used_bytes: usize,
+    /// Size of the XMP data after the conversion, excluding padding, in bytes
+    pub new_used_bytes: usize,
+}
+
+impl ApplyStats {
+    /// Write speed, in KB/s
+    pub fn write_speed(&self) -> f64 {
+        let secs = self.write_duration.as_secs_f64();
+
+        if secs > 0. {
+            self.new_used_bytes as f64 / 1024. / secs
+        } else {
+            0.
+        }
+    }
+}
+
+impl FileState {
+    /// Returns the inner error if this state is an error state, along with the path of the file
+    #[allow(clippy::wrong_self_convention)]
+    pub fn into_error(&self) -> Option<Box<dyn std::error::Error + Send + Sync>> {
+        match self {
+            FileState::IoError(error) => Some(Box::new(error.clone())),
+            FileState::ContainerError(error) => Some(Box::new(error.clone())),
+            FileState::XmpRewriteError(error) => Some(Box::new(error.clone())),
+            FileState::InvalidAcdseeData(error) => Some(Box::new(error.clone())),
+            FileState::RewriteError(error) => Some(Box::new(error.clone())),
+            FileState::ApplyError(error) => Some(Box::new(error.clone())),
+            FileState::BackupError(error) => Some(Box::new(error.clone())),
+            FileState::Init
+            | FileState::NoXmpData
+            | FileState::NoAcdData
+            | FileState::AlreadyConverted
+            | FileState::PartialAcdData(_, _)
+            | FileState::Ready(_, _)
+            | FileState::Complete(_, _) => None,
+        }
+    }
+
+    /// Returns the ACDSee data read from the file, if this state holds it
+    pub fn acdsee_data(&self) -> Option<&Arc<AcdSeeData>> {
+        match self {
+            FileState::Ready(_, acd)
+            | FileState::PartialAcdData(_, acd)
+            | FileState::Complete(_, acd) => Some(acd),
+            _ => None,
+        }
+    }
+
+    /// Converts this state into the rewritten packet, if any, or the error that prevented it
+    pub fn into_result(
+        self,
+    ) -> Result<Option<Arc<Vec<u8>>>, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(error) = self.into_error() {
+            return Err(error);
+        }
+
+        match self {
+            FileState::Ready(packet, _) => Ok(Some(packet)),
+            _ => Ok(None),
+        }
+    }
 }
 
 impl std::fmt::Display for FileState {
@@ -30,41 +111,38 @@ impl std::fmt::Display for FileState {
         // TODO: Translate from english
         match self {
             FileState::Init => write!(f, "En attente"),
-            FileState::IoError(error) => write!(f, "Erreur E/S: {}", error),
+            FileState::IoError(error) => write!(f, "Erreur E/S: {}", error.inner()),
             FileState::NoXmpData => write!(f, "Aucune donnée XMP présente"),
             FileState::NoAcdData => write!(f, "Aucune donnée ACDSee présente"),
-            FileState::ContainerError(error) => write!(f, "Erreur de lecture: {}", error),
-            FileState::XmpRewriteError(error) => write!(f, "Erreur d'écriture: {}", error),
-            FileState::InvalidAcdseeData(error) => write!(f, "Données ACDSee invalides: {}", error),
-            FileState::Ready(_) => write!(f, "Prêt pour la réecriture"),
+            FileState::AlreadyConverted => write!(f, "Déjà converti"),
+            FileState::ContainerError(error) => write!(f, "Erreur de lecture: {}", error.inner()),
+            FileState::XmpRewriteError(error) => write!(f, "Erreur d'écriture: {}", error.inner()),
+            FileState::InvalidAcdseeData(error) => {
+                write!(f, "Données ACDSee invalides: {}", error.inner())
+            }
+            FileState::PartialAcdData(warning, _) => {
+                write!(f, "Prêt pour la réecriture (champs ignorés: {})", warning)
+            }
+            FileState::Ready(_, _) => write!(f, "Prêt pour la réecriture"),
             FileState::RewriteError(error) => {
-                write!(f, "Erreur de préparation à la réecriture: {}", error)
+                write!(
+                    f,
+                    "Erreur de préparation à la réecriture: {}",
+                    error.inner()
+                )
+            }
+            FileState::Complete(_, _) => write!(f, "Succès"),
+            FileState::ApplyError(error) => write!(f, "Erreur de réecriture: {}", error.inner()),
+            FileState::BackupError(error) => {
+                write!(f, "Impossible de sauvegarder: {}", error.inner())
             }
-            FileState::Complete => write!(f, "Succès"),
-            FileState::ApplyError(error) => write!(f, "Erreur de réecriture: {}", error),
-            FileState::BackupError(error) => write!(f, "Impossible de sauvegarder: {}", error),
-        }
-    }
-}
-
-impl From<Result<FileState, ContainerError>> for FileState {
-    fn from(result: Result<FileState, ContainerError>) -> Self {
-        match result {
-            Ok(result) => result,
-            Err(error) => Self::ContainerError(Arc::new(error)),
         }
     }
 }
 
-impl From<std::io::Error> for FileState {
-    fn from(io: std::io::Error) -> Self {
-        Self::IoError(Arc::new(io))
-    }
-}
-
-impl From<ContainerWriteError> for FileState {
-    fn from(e: ContainerWriteError) -> Self {
-        Self::ApplyError(Arc::new(e))
+impl std::fmt::Display for FileStateKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_ref())
     }
 }
 
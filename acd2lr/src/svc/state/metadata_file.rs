@@ -1,27 +1,76 @@
 use std::{
     cmp::Ordering,
     convert::TryFrom,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
     sync::Arc,
-    time::SystemTime,
+    time::{Duration, Instant, SystemTime},
 };
 
 use async_std::fs::{File, OpenOptions};
 use thiserror::Error;
 
 use acd2lr_core::{
-    container::{Container, ContainerError},
-    xmp::rules,
+    acdsee::{AcdSeeData, AcdSeeError, FieldMapping},
+    container::{Container, ContainerError, ContainerRewriteError},
+    convert::{self, Conversion},
+    xmp::{WriteError, XmpData},
+    xpacket::XPacket,
 };
 
-use super::{BackupMode, FileState};
+use super::{ApplyStats, BackupMode, FileState};
 
-pub const SUPPORTED_EXTS: &[&str] = &["jpeg", "jpg", "tif", "tiff", "xmp", "xpacket"];
+pub const SUPPORTED_EXTS: &[&str] = &[
+    "arw", "cr2", "cr3", "dng", "jpeg", "jpg", "nef", "orf", "pef", "png", "raf", "rw2", "tif",
+    "tiff", "webp", "xmp", "xpacket",
+];
 
-#[derive(Debug)]
+/// Returns true if the extension of `path` is one of the supported extensions
+pub fn is_supported(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase())
+        .map(|ext| SUPPORTED_EXTS.binary_search(&ext.as_str()).is_ok())
+        .unwrap_or(false)
+}
+
+/// Size of the XMP data in `bytes`, excluding the XPacket wrapper and the padding
+fn used_bytes(bytes: &[u8]) -> usize {
+    match XPacket::try_from(bytes) {
+        Ok(xpacket) => xpacket.used_bytes(),
+        Err(_) => bytes
+            .iter()
+            .rposition(|b| !b.is_ascii_whitespace())
+            .map(|i| i + 1)
+            .unwrap_or(0),
+    }
+}
+
+/// Size of the file at `path` in bytes, or `None` if it cannot be read
+async fn file_size(path: &Path) -> Option<u64> {
+    async_std::fs::metadata(path)
+        .await
+        .map(|metadata| metadata.len())
+        .ok()
+}
+
+/// Returns the DNG file `path` is a sidecar of, if `path` is named like `name.dng.xmp`
+fn dng_of_sidecar(path: &Path) -> Option<PathBuf> {
+    let dng = path.with_extension("");
+
+    if path.extension()?.eq_ignore_ascii_case("xmp") && dng.extension()?.eq_ignore_ascii_case("dng")
+    {
+        Some(dng)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct MetadataFile {
     path: Arc<PathBuf>,
     last_check: Option<std::time::SystemTime>,
+    /// Size of the file at the last check, in bytes
+    size: Option<u64>,
     state: FileState,
 }
 
@@ -30,13 +79,76 @@ impl MetadataFile {
         self.path.as_path()
     }
 
+    /// Shared path of this file
+    pub fn shared_path(&self) -> &Arc<PathBuf> {
+        &self.path
+    }
+
     pub fn state(&self) -> &FileState {
         &self.state
     }
 
+    /// Size of the file at the last check, in bytes, or `None` if it was not checked yet
+    pub fn size(&self) -> Option<u64> {
+        self.size
+    }
+
+    /// Modification time of the file at the last check, or `None` if it was not checked yet
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.last_check
+    }
+
+    /// Returns the error of the current state, if any
+    ///
+    /// The error holds the path of this file.
+    pub fn error(&self) -> Option<Box<dyn std::error::Error + Send + Sync>> {
+        self.state.into_error()
+    }
+
+    /// Returns `true` if the file was not modified since its last check
+    pub fn unchanged_since_check(&self) -> bool {
+        match self.last_check {
+            Some(last_check) => std::fs::metadata(&*self.path)
+                .and_then(|metadata| metadata.modified())
+                .map(|modified| modified == last_check)
+                .unwrap_or(false),
+            None => false,
+        }
+    }
+
+    /// Attach the path of this file to `error`
+    fn context<E>(&self, error: E) -> Arc<PathContext<E>> {
+        Arc::new(PathContext::new(self.path.clone(), error))
+    }
+
+    /// Prepare the rewritten packet for the given ACDSee data
+    async fn prepare_packet(
+        &self,
+        container: &mut Container,
+        xmp: &XmpData,
+        acd: &AcdSeeData,
+        mapping: &FieldMapping,
+    ) -> FileState {
+        match convert::convert_xmp(xmp, acd, mapping) {
+            Ok(Conversion::NoAcdData) => FileState::NoAcdData,
+            Ok(Conversion::AlreadyConverted) => FileState::AlreadyConverted,
+            Ok(Conversion::Converted(rewritten)) => {
+                // We have an XML event stream ready, try to prepare the rewritten content
+                match container.prepare_write(&rewritten).await {
+                    // Everything works, including the rewrite back to the file
+                    Ok(packet) => FileState::Ready(Arc::new(packet), Arc::new(acd.clone())),
+                    // Failed the last part
+                    Err(error) => FileState::RewriteError(self.context(error)),
+                }
+            }
+            Err(error) => FileState::XmpRewriteError(self.context(error)),
+        }
+    }
+
     async fn get_rewrite_state(
         &self,
         file: File,
+        mapping: &FieldMapping,
     ) -> Result<(FileState, File), (ContainerError, File)> {
         // Open the container
         let mut container = Container::open(file)
@@ -51,87 +163,154 @@ impl MetadataFile {
             }
         };
 
-        if let Some(xmp) = data {
+        let state = if let Some((xmp, _)) = data {
             // Try to read the acdsee data
-            match xmp.acdsee_data() {
-                Ok(acd) => {
-                    // We have some data, check if it requires rewrites?
-                    let mut rules = acd.to_ruleset();
-                    if rules.is_empty() {
-                        return Ok((FileState::NoAcdData, container.into_inner()));
-                    } else {
-                        // There are some rules, so try to apply them
-                        rules.push(rules::xmp_metadata_date());
-
-                        match xmp.write_events(rules) {
-                            Ok(rewritten) => {
-                                // We have an XML event stream ready, try to prepare the rewritten content
-                                match container.prepare_write(&rewritten).await {
-                                    Ok(packet) => {
-                                        // Everything works, including the rewrite back to the file
-                                        Ok((
-                                            FileState::Ready(Arc::new(packet)),
-                                            container.into_inner(),
-                                        ))
-                                    }
-                                    Err(error) => {
-                                        // Failed the last part
-                                        Ok((
-                                            FileState::RewriteError(Arc::new(error)),
-                                            container.into_inner(),
-                                        ))
-                                    }
-                                }
-                            }
-                            Err(error) => Ok((
-                                FileState::XmpRewriteError(Arc::new(error)),
-                                container.into_inner(),
-                            )),
+            match convert::read_acdsee_data(&xmp) {
+                Ok((acd, warning)) => {
+                    match (
+                        self.prepare_packet(&mut container, &xmp, &acd, mapping)
+                            .await,
+                        warning,
+                    ) {
+                        (FileState::Ready(_, acd), Some(warning)) => {
+                            FileState::PartialAcdData(Arc::new(warning), acd)
                         }
+                        (other, _) => other,
                     }
                 }
-                Err(error) => Ok((
-                    FileState::InvalidAcdseeData(Arc::new(error)),
-                    container.into_inner(),
-                )),
+                Err(error) => FileState::InvalidAcdseeData(self.context(error)),
             }
         } else {
-            Ok((FileState::NoXmpData, container.into_inner()))
+            FileState::NoXmpData
+        };
+
+        Ok((state, container.into_inner()))
+    }
+
+    /// Read the ACDSee data of this file, or `None` if the file has no XMP data
+    pub async fn read_acdsee_data(&self) -> Result<Option<AcdSeeData>, PreviewError> {
+        let file = File::open(&*self.path)
+            .await
+            .map_err(ContainerError::from)?;
+        let mut container = Container::open(file)
+            .await
+            .map_err(|(e, _)| ContainerError::from(e))?;
+
+        match container.read_xmp().await? {
+            Some((xmp, _)) => Ok(Some(xmp.acdsee_data()?)),
+            None => Ok(None),
         }
     }
 
-    async fn check_rewrite_inner(&self) -> (FileState, Option<std::time::SystemTime>) {
+    /// Read the XMP data of this file, without applying any conversion
+    ///
+    /// Returns an empty string if the file has no XMP data.
+    pub async fn preview_xmp(&self) -> Result<String, ContainerError> {
+        let file = File::open(&*self.path).await?;
+        let mut container = Container::open(file).await.map_err(|(e, _)| e)?;
+
+        match container.read_xmp().await? {
+            Some((_, bytes)) => Ok(String::from_utf8_lossy(&bytes).into_owned()),
+            None => Ok(String::new()),
+        }
+    }
+
+    /// Convert the XMP data of this file, without writing it back
+    ///
+    /// Returns the XMP data that would be written by [`MetadataFile::apply`], the current XMP
+    /// data if there is nothing to convert, or an empty string if the file has no XMP data.
+    pub async fn preview_converted_xmp(
+        &self,
+        mapping: &FieldMapping,
+    ) -> Result<String, PreviewError> {
+        let file = File::open(&*self.path)
+            .await
+            .map_err(ContainerError::from)?;
+        let mut container = Container::open(file)
+            .await
+            .map_err(|(e, _)| ContainerError::from(e))?;
+
+        let (xmp, bytes) = match container.read_xmp().await? {
+            Some(data) => data,
+            None => return Ok(String::new()),
+        };
+
+        // Same conversion as get_rewrite_state
+        let (acd, _) = convert::read_acdsee_data(&xmp)?;
+
+        match self
+            .prepare_packet(&mut container, &xmp, &acd, mapping)
+            .await
+        {
+            FileState::Ready(packet, _) => Ok(String::from_utf8(packet.to_vec())?),
+            FileState::XmpRewriteError(error) => Err(PreviewError::Write(error)),
+            FileState::RewriteError(error) => Err(PreviewError::Rewrite(error)),
+            // Nothing to convert, the file would be left untouched
+            _ => Ok(String::from_utf8_lossy(&bytes).into_owned()),
+        }
+    }
+
+    async fn check_rewrite_inner(
+        &self,
+        mapping: &FieldMapping,
+    ) -> (FileState, Option<std::time::SystemTime>) {
         // Open the file
         match File::open(&*self.path).await {
             Ok(file) => match file.metadata().await {
                 Ok(metadata) => match metadata.modified() {
                     Ok(modified) => (
-                        self.get_rewrite_state(file)
-                            .await
-                            .map(|(s, _)| s)
-                            .map_err(|(e, _)| e)
-                            .into(),
+                        match self.get_rewrite_state(file, mapping).await {
+                            Ok((state, _)) => state,
+                            Err((error, _)) => FileState::ContainerError(self.context(error)),
+                        },
                         Some(modified),
                     ),
-                    Err(error) => (error.into(), None),
+                    Err(error) => (FileState::IoError(self.context(error)), None),
                 },
-                Err(error) => (error.into(), None),
+                Err(error) => (FileState::IoError(self.context(error)), None),
             },
-            Err(error) => (error.into(), None),
+            Err(error) => (FileState::IoError(self.context(error)), None),
         }
     }
 
-    pub async fn check_rewrite(&self) -> Self {
+    /// Check if this file can be converted
+    ///
+    /// If the file was not modified since the last check, the known state is returned.
+    pub async fn check_rewrite(&self, mapping: &FieldMapping) -> Self {
+        if let Some(last_check) = self.last_check {
+            let modified = async_std::fs::metadata(&*self.path)
+                .await
+                .and_then(|metadata| metadata.modified());
+
+            if matches!(modified, Ok(modified) if modified == last_check) {
+                tracing::debug!(path = %self.path().display(), "file unchanged, skipping check");
+                return self.clone();
+            }
+        }
+
+        self.force_check_rewrite(mapping).await
+    }
+
+    /// Check if this file can be converted, even if it was not modified since the last check
+    pub async fn force_check_rewrite(&self, mapping: &FieldMapping) -> Self {
         // No state check, since we can always check a rewrite
 
         let path = self.path.clone();
-        let (result, modified) = self.check_rewrite_inner().await;
+        let (result, modified) = self.check_rewrite_inner(mapping).await;
+        let size = file_size(&path).await;
 
-        Self {
+        let file = Self {
             path,
             last_check: modified,
+            size,
             state: result,
+        };
+
+        if let Some(error) = file.error() {
+            tracing::warn!(%error, "check failed");
         }
+
+        file
     }
 
     fn backup_path(&self) -> PathBuf {
@@ -179,19 +358,26 @@ impl MetadataFile {
         file: File,
         modified: SystemTime,
         backup_mode: BackupMode,
+        mapping: &FieldMapping,
     ) -> FileState {
         // Check if we need to check_rewrite first
+        let check_start = Instant::now();
         let reread_state;
-        let (state, file) = if self
+        let stale = self
             .last_check
             .map(|known_modified| modified > known_modified)
-            .unwrap_or(true)
-        {
-            // The file was modified, thus the known state is stale
+            .unwrap_or(true);
+        let ready = matches!(
+            self.state(),
+            FileState::Ready(_, _) | FileState::PartialAcdData(_, _)
+        );
+
+        let (state, file) = if stale || !ready {
+            // The file was modified or failed before, thus the known state is stale
             // Try to rewrite it first
-            let (state, file) = match self.get_rewrite_state(file).await {
-                Ok((res, file)) => (FileState::from(Ok(res)), file),
-                Err((err, file)) => (FileState::from(Err(err)), file),
+            let (state, file) = match self.get_rewrite_state(file, mapping).await {
+                Ok((res, file)) => (res, file),
+                Err((err, file)) => (FileState::ContainerError(self.context(err)), file),
             };
 
             reread_state = state;
@@ -202,103 +388,248 @@ impl MetadataFile {
 
         // If the new state is ready, we can proceed
         match state {
-            FileState::Ready(bytes) => {
-                // Backup the file first
-                match self.backup(backup_mode).await {
-                    Ok(_) => {}
-                    Err(e) => {
-                        return FileState::BackupError(Arc::new(e));
-                    }
-                }
-
-                // Open the container
+            FileState::Ready(bytes, acd) => {
+                self.write_packet(file, bytes, acd, backup_mode, check_start.elapsed())
+                    .await
+            }
+            FileState::PartialAcdData(_, acd) => {
+                // The packet is not kept for partial data, prepare it again
                 let mut container = match Container::open(file).await {
                     Ok(container) => container,
                     Err((e, _)) => {
-                        return e.into();
+                        return FileState::IoError(self.context(e));
                     }
                 };
 
-                // Write the data
-                match container.write(&bytes[..]).await {
-                    Ok(_) => FileState::Complete,
-                    Err(e) => e.into(),
+                let xmp = match container.read_xmp().await {
+                    Ok(Some((xmp, _))) => xmp,
+                    Ok(None) => return FileState::NoXmpData,
+                    Err(e) => return FileState::ContainerError(self.context(e)),
+                };
+
+                match self
+                    .prepare_packet(&mut container, &xmp, acd, mapping)
+                    .await
+                {
+                    FileState::Ready(bytes, _) => {
+                        self.write_packet(
+                            container.into_inner(),
+                            &bytes,
+                            acd,
+                            backup_mode,
+                            check_start.elapsed(),
+                        )
+                        .await
+                    }
+                    other => other,
                 }
             }
             other => other.clone(),
         }
     }
 
+    async fn write_packet(
+        &self,
+        file: File,
+        bytes: &[u8],
+        acd: &Arc<AcdSeeData>,
+        backup_mode: BackupMode,
+        check_duration: Duration,
+    ) -> FileState {
+        // Open the container
+        let mut container = match Container::open(file).await {
+            Ok(container) => container,
+            Err((e, _)) => {
+                return FileState::IoError(self.context(e));
+            }
+        };
+
+        let original_used_bytes = match container.read_xmp().await {
+            Ok(Some((_, original))) => used_bytes(&original),
+            Ok(None) => 0,
+            Err(e) => return FileState::ContainerError(self.context(e)),
+        };
+
+        // Only back up the file if it is going to be written
+        match container.is_unchanged(bytes).await {
+            Ok(true) => {
+                tracing::debug!(path = %self.path().display(), "packet unchanged, file not written");
+            }
+            Ok(false) => {
+                if let Err(e) = self.backup(backup_mode).await {
+                    return FileState::BackupError(self.context(e));
+                }
+            }
+            Err(e) => return FileState::ApplyError(self.context(e)),
+        }
+
+        // Write the data, which leaves an unchanged file untouched
+        let write_start = Instant::now();
+        match container.write(bytes).await {
+            Ok(_) => {
+                let write_duration = write_start.elapsed();
+
+                FileState::Complete(
+                    Arc::new(ApplyStats {
+                        check_duration,
+                        write_duration,
+                        original_used_bytes,
+                        new_used_bytes: used_bytes(bytes),
+                    }),
+                    acd.clone(),
+                )
+            }
+            Err(e) => FileState::ApplyError(self.context(e)),
+        }
+    }
+
+    /// Path of the converted file when writing to `output_dir`
+    ///
+    /// The directory structure of the original file is mirrored under `output_dir`.
+    fn output_path(&self, output_dir: &Path) -> PathBuf {
+        let mut target_path = output_dir.to_path_buf();
+        target_path.extend(
+            self.path()
+                .components()
+                .filter(|component| matches!(component, Component::Normal(_))),
+        );
+
+        target_path
+    }
+
+    async fn prepare_output(&self, output_dir: &Path) -> Result<PathBuf, std::io::Error> {
+        let target_path = self.output_path(output_dir);
+
+        // Create the mirrored directory structure
+        if let Some(parent) = target_path.parent() {
+            async_std::fs::create_dir_all(parent).await?;
+        }
+
+        // Start from a copy of the original, which is then rewritten in place
+        async_std::fs::copy(self.path(), &target_path).await?;
+
+        Ok(target_path)
+    }
+
     async fn apply_inner(
         &self,
         backup_mode: BackupMode,
+        output_dir: Option<&Path>,
+        mapping: &FieldMapping,
     ) -> (FileState, Option<std::time::SystemTime>) {
+        // Find out which file we are writing to
+        let (target_path, backup_mode) = match output_dir {
+            Some(output_dir) => match self.prepare_output(output_dir).await {
+                // The original file is left untouched, no need to back it up
+                Ok(target_path) => (target_path, BackupMode::NoBackups),
+                Err(error) => return (FileState::IoError(self.context(error)), None),
+            },
+            None => (self.path().to_path_buf(), backup_mode),
+        };
+
         // Open the file r/w
         match OpenOptions::new()
             .read(true)
             .write(true)
-            .open(&*self.path)
+            .open(&target_path)
             .await
         {
             Ok(file) => match file.metadata().await {
                 Ok(metadata) => match metadata.modified() {
                     Ok(modified) => (
-                        self.get_apply_state(file, modified, backup_mode)
-                            .await
-                            .into(),
+                        self.get_apply_state(file, modified, backup_mode, mapping)
+                            .await,
                         Some(modified),
                     ),
-                    Err(error) => (error.into(), None),
+                    Err(error) => (FileState::IoError(self.context(error)), None),
                 },
-                Err(error) => (error.into(), None),
+                Err(error) => (FileState::IoError(self.context(error)), None),
             },
-            Err(error) => (error.into(), None),
+            Err(error) => (FileState::IoError(self.context(error)), None),
         }
     }
 
-    pub async fn apply(&self, backup_mode: BackupMode) -> Self {
+    /// Apply the conversion
+    ///
+    /// If `output_dir` is set, the converted file is written to a mirrored path under this folder
+    /// instead of overwriting the original file.
+    pub async fn apply(
+        &self,
+        backup_mode: BackupMode,
+        output_dir: Option<&Path>,
+        mapping: &FieldMapping,
+    ) -> Self {
         let path = self.path.clone();
-        let (result, modified) = self.apply_inner(backup_mode).await;
+        let (result, mut modified) = self.apply_inner(backup_mode, output_dir, mapping).await;
+        let mut size = self.size;
+
+        if output_dir.is_none() && matches!(result, FileState::Complete(_, _)) {
+            // The file was just written, so the check must not see it as modified
+            let metadata = async_std::fs::metadata(&*path).await.ok();
+            modified = metadata
+                .as_ref()
+                .and_then(|metadata| metadata.modified().ok());
+            size = metadata.map(|metadata| metadata.len());
+        }
 
-        Self {
+        let file = Self {
             path,
             last_check: modified,
+            size,
             state: result,
+        };
+
+        if let Some(error) = file.error() {
+            tracing::warn!(%error, "apply failed");
         }
+
+        file
     }
 
+    /// Collect the files to process at `path`, which may be a single file or a folder
+    pub fn from_path(path: PathBuf) -> Vec<Result<Arc<Self>, FileError>> {
+        if path.is_dir() {
+            Self::from_dir(&path)
+        } else {
+            vec![Self::try_from(path).map(Arc::new)]
+        }
+    }
+
+    /// Iterate over the supported files in `dir` and its subfolders, in directory order
+    ///
+    /// Folders are only read as the iteration reaches them.
+    pub fn from_dir_iter(dir: &Path) -> impl Iterator<Item = Result<Arc<Self>, FileError>> {
+        match std::fs::read_dir(dir) {
+            Ok(read_dir) => DirFiles {
+                stack: vec![read_dir],
+                error: None,
+            },
+            Err(error) => DirFiles {
+                stack: Vec::new(),
+                error: Some(FileError::OpenDir(PathContext::new(
+                    Arc::new(dir.to_path_buf()),
+                    error,
+                ))),
+            },
+        }
+    }
+
+    /// Collect the supported files in `dir` and its subfolders, sorted by path
     pub fn from_dir(dir: &Path) -> Vec<Result<Arc<Self>, FileError>> {
-        let mut result = Vec::new();
-
-        match std::fs::read_dir(&dir) {
-            Ok(read_dir) => {
-                for file in read_dir {
-                    match file {
-                        Ok(file) => {
-                            let path = file.path();
-                            if path.is_file() {
-                                if let Some(ext) = path
-                                    .extension()
-                                    .and_then(|ext| ext.to_str())
-                                    .map(|ext| ext.to_ascii_lowercase())
-                                {
-                                    if SUPPORTED_EXTS.binary_search(&ext.as_str()).is_ok() {
-                                        result.push(Self::try_from(path).map(Arc::new));
-                                    }
-                                }
-                            } else {
-                                result.extend(Self::from_dir(&path));
-                            }
-                        }
-                        Err(error) => {
-                            result.push(Err(FileError::OpenFile(error)));
-                        }
-                    }
-                }
-            }
-            Err(error) => {
-                result.push(Err(FileError::OpenDir(error)));
-            }
+        let mut result: Vec<_> = Self::from_dir_iter(dir).collect();
+
+        // When a DNG file has a .dng.xmp sidecar, the sidecar holds the metadata to convert
+        let sidecar_dngs: Vec<_> = result
+            .iter()
+            .filter_map(|file| file.as_ref().ok())
+            .filter_map(|file| dng_of_sidecar(file.path()))
+            .collect();
+
+        if !sidecar_dngs.is_empty() {
+            result.retain(|file| match file {
+                Ok(file) => !sidecar_dngs.iter().any(|dng| dng == file.path()),
+                Err(_) => true,
+            });
         }
 
         result.sort_by(|a, b| match (a, b) {
@@ -312,6 +643,52 @@ impl MetadataFile {
     }
 }
 
+/// Depth-first iterator over the supported files in a folder
+struct DirFiles {
+    stack: Vec<std::fs::ReadDir>,
+    error: Option<FileError>,
+}
+
+impl Iterator for DirFiles {
+    type Item = Result<Arc<MetadataFile>, FileError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(error) = self.error.take() {
+            return Some(Err(error));
+        }
+
+        loop {
+            match self.stack.last_mut()?.next() {
+                Some(Ok(file)) => {
+                    let path = file.path();
+                    if path.is_file() {
+                        if is_supported(&path) {
+                            return Some(MetadataFile::try_from(path).map(Arc::new));
+                        }
+                    } else {
+                        match std::fs::read_dir(&path) {
+                            Ok(read_dir) => self.stack.push(read_dir),
+                            Err(error) => {
+                                return Some(Err(FileError::OpenDir(PathContext::new(
+                                    Arc::new(path),
+                                    error,
+                                ))))
+                            }
+                        }
+                    }
+                }
+                Some(Err(error)) => {
+                    return Some(Err(FileError::OpenFile(error)));
+                }
+                None => {
+                    // Done with this folder
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
 impl TryFrom<PathBuf> for MetadataFile {
     type Error = FileError;
 
@@ -319,15 +696,68 @@ impl TryFrom<PathBuf> for MetadataFile {
         Ok(Self {
             path: Arc::new(value),
             last_check: None,
+            size: None,
             state: Default::default(),
         })
     }
 }
 
+/// An error, along with the path of the file it happened on
+#[derive(Debug)]
+pub struct PathContext<E> {
+    path: Arc<PathBuf>,
+    source: E,
+}
+
+impl<E> PathContext<E> {
+    pub fn new(path: Arc<PathBuf>, source: E) -> Self {
+        Self { path, source }
+    }
+
+    pub fn path(&self) -> &Path {
+        self.path.as_path()
+    }
+
+    /// The error, without its path
+    pub fn inner(&self) -> &E {
+        &self.source
+    }
+
+    pub fn into_inner(self) -> E {
+        self.source
+    }
+}
+
+impl<E: std::fmt::Display> std::fmt::Display for PathContext<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.source)
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for PathContext<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum FileError {
-    #[error("cannot open dir: {}", 0)]
-    OpenDir(std::io::Error),
-    #[error("cannot open file: {}", 0)]
+    #[error("cannot open dir {0}")]
+    OpenDir(PathContext<std::io::Error>),
+    #[error("cannot open file: {0}")]
     OpenFile(std::io::Error),
 }
+
+#[derive(Debug, Error)]
+pub enum PreviewError {
+    #[error(transparent)]
+    Container(#[from] ContainerError),
+    #[error(transparent)]
+    AcdSee(#[from] AcdSeeError),
+    #[error(transparent)]
+    Write(Arc<PathContext<WriteError>>),
+    #[error(transparent)]
+    Rewrite(Arc<PathContext<ContainerRewriteError>>),
+    #[error("invalid UTF-8 in the converted XMP data: {0}")]
+    Utf8(#[from] std::string::FromUtf8Error),
+}
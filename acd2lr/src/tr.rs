@@ -1,13 +1,32 @@
+use std::path::Path;
+
+use tracing::level_filters::LevelFilter;
+use tracing_appender::non_blocking::WorkerGuard;
+
 use crate::{Message, MessageSender};
 
-pub fn install(tx: MessageSender) {
+/// Install the tracing subscriber, forwarding UI events to `tx` if set
+///
+/// If `log_path` is set, events are also appended to the given file. The returned guard must be
+/// kept alive for as long as events should be written to this file.
+///
+/// If `level` is set, it overrides the `RUST_LOG` environment variable.
+pub fn install_with_file(
+    tx: Option<MessageSender>,
+    log_path: Option<&Path>,
+    level: Option<LevelFilter>,
+) -> Option<WorkerGuard> {
     use tracing_error::ErrorLayer;
     use tracing_subscriber::prelude::*;
     use tracing_subscriber::{fmt, EnvFilter};
 
-    let filter_layer = EnvFilter::try_from_default_env()
-        .or_else(|_| EnvFilter::try_new("info"))
-        .unwrap();
+    let filter_layer = if let Some(level) = level {
+        EnvFilter::default().add_directive(level.into())
+    } else {
+        EnvFilter::try_from_default_env()
+            .or_else(|_| EnvFilter::try_new("info"))
+            .unwrap()
+    };
     let fmt_layer = fmt::layer()
         .with_target(false)
         .with_span_events(tracing_subscriber::fmt::format::FmtSpan::ENTER);
@@ -66,11 +85,40 @@ pub fn install(tx: MessageSender) {
         }
     }
 
+    // Open the log file, if any
+    let log_file = log_path.map(|path| {
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|error| (path, error))
+    });
+
+    let (file_layer, guard, file_error) = match log_file {
+        Some(Ok(file)) => {
+            let (file_writer, guard) = tracing_appender::non_blocking(file);
+            (
+                Some(fmt::layer().with_ansi(false).with_writer(file_writer)),
+                Some(guard),
+                None,
+            )
+        }
+        Some(Err(error)) => (None, None, Some(error)),
+        None => (None, None, None),
+    };
+
     tracing_subscriber::registry()
         .with(filter_layer)
         .with(fmt_layer)
-        .with(ForwardLayer { tx })
+        .with(file_layer)
+        .with(tx.map(|tx| ForwardLayer { tx }))
         .with(ErrorLayer::default())
         .try_init()
         .ok();
+
+    if let Some((path, error)) = file_error {
+        tracing::warn!(path = %path.display(), %error, "failed to open log file");
+    }
+
+    guard
 }
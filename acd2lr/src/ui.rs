@@ -1,40 +1,68 @@
-use std::{cell::RefCell, convert::TryInto, ffi::OsString, path::PathBuf, rc::Rc};
+use std::{cell::RefCell, convert::TryInto, ffi::OsString, path::PathBuf, rc::Rc, sync::Arc};
+
+use chrono::TimeZone;
 
 use gdk_pixbuf::prelude::*;
 use gio::prelude::*;
 use glib::clone;
 use gtk::{
-    prelude::*, ApplicationWindow, Builder, Button, ComboBox, FileChooserNative, ListBox, MenuItem,
-    ProgressBar, Statusbar,
+    prelude::*, ApplicationWindow, Builder, Button, ComboBox, FileChooserNative, Inhibit, MenuItem,
+    ProgressBar, Statusbar, ToggleButton, TreeView,
 };
 
 mod row_data;
 use row_data::RowData;
 
-use crate::svc::*;
+use crate::{config::Config, svc::*};
+
+/// Application name recorded for the paths added to the recent files
+pub const RECENT_APPLICATION_NAME: &str = "acd2lr";
+
+// Columns of the file list model, see liststore_files in main.glade
+const COLUMN_FILE: u32 = 0;
+const COLUMN_FILENAME: u32 = 1;
+const COLUMN_DIRECTORY: u32 = 2;
+const COLUMN_STATE: u32 = 3;
+const COLUMN_STATE_KIND: u32 = 4;
+const COLUMN_SIZE: u32 = 5;
+const COLUMN_SIZE_TEXT: u32 = 6;
+const COLUMN_ICON: u32 = 7;
+const COLUMN_TOOLTIP: u32 = 8;
+const COLUMN_MODIFIED: u32 = 9;
+const COLUMN_MODIFIED_TEXT: u32 = 10;
+const COLUMN_FOREGROUND: u32 = 11;
 
 #[derive(Clone)]
 pub struct Ui {
     window: ApplicationWindow,
-    service: Rc<RefCell<Option<ServiceHandle>>>,
+    service: SharedServiceHandle,
+    tx: MessageSender,
     builder: Builder,
+    /// Timer pulsing the progress bar while the number of files is unknown
+    pulse: Rc<RefCell<Option<glib::SourceId>>>,
 }
 
 impl Ui {
     pub fn new(
         window: ApplicationWindow,
-        service: Rc<RefCell<Option<ServiceHandle>>>,
+        service: SharedServiceHandle,
+        tx: MessageSender,
         builder: Builder,
     ) -> Self {
         Self {
             window,
             service,
+            tx,
             builder,
+            pulse: Default::default(),
         }
     }
 
-    fn open_callback<T>(self, filechooser: FileChooserNative) -> impl for<'r> Fn(&'r T) -> () {
-        move |_: &_| {
+    fn open_callback(
+        self,
+        filechooser: FileChooserNative,
+    ) -> impl Fn(&gio::SimpleAction, Option<&glib::Variant>) {
+        move |_, _| {
             filechooser.run();
 
             let filenames = filechooser.get_filenames();
@@ -46,19 +74,343 @@ impl Ui {
         if !filenames.is_empty() {
             self.window.set_sensitive(false);
 
-            if let Some(service) = &*self.service.borrow() {
-                service.send_request(Request::OpenPaths(filenames));
+            // Remember the opened paths in the recent files
+            let manager = gtk::RecentManager::get_default();
+            for filename in &filenames {
+                match glib::filename_to_uri(filename, None) {
+                    Ok(uri) => {
+                        manager.add_item(&uri);
+                    }
+                    Err(error) => {
+                        warn!(path = %filename.display(), %error, "cannot add to recent files");
+                    }
+                }
+            }
+
+            if send_request(&self.service, Request::OpenPaths(filenames)) {
+                self.start_pulse();
+            } else {
+                self.window.set_sensitive(true);
+            }
+        }
+    }
+
+    /// Pulse the progress bar until the scanned files are known
+    fn start_pulse(&self) {
+        if self.pulse.borrow().is_some() {
+            return;
+        }
+
+        let progress: ProgressBar = self.builder.get_object("progressbar").unwrap();
+        progress.set_fraction(0.);
+
+        *self.pulse.borrow_mut() = Some(glib::timeout_add_local(100, move || {
+            progress.pulse();
+            glib::Continue(true)
+        }));
+    }
+
+    /// Stop pulsing the progress bar, if it was
+    fn stop_pulse(&self, progress: &ProgressBar) {
+        if let Some(source_id) = self.pulse.borrow_mut().take() {
+            glib::source_remove(source_id);
+            progress.set_fraction(0.);
+        }
+    }
+
+    /// Restart the backend service if it stopped
+    fn restart_dead_service(&self, file_list: &gtk::ListStore) {
+        if let Some(service) = &mut *self.service.lock().unwrap() {
+            if service.is_alive() {
+                return;
+            }
+
+            // The previous service state is lost, so start again from an empty list
+            file_list.clear();
+            service.restart(self.tx.clone());
+
+            info!(
+                ui = true,
+                "Service de traitement redémarré, la liste des fichiers a été vidée"
+            );
+        }
+    }
+
+    /// Restart the backend service if it stopped without reporting an error
+    ///
+    /// Reported failures are left to the fatal error dialog, so the user chooses whether to restart.
+    fn check_service(&self, file_list: &gtk::ListStore) {
+        let stopped_silently = self
+            .service
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|service| !service.is_alive() && !service.failure_reported())
+            .unwrap_or(false);
+
+        if stopped_silently {
+            warn!("backend service stopped without reporting an error");
+            self.restart_dead_service(file_list);
+        }
+    }
+
+    /// Fill the recent files menu from the paths previously opened by acd2lr
+    fn update_recent_menu(&self, menuitem_recent: &MenuItem, menu_recent: &gtk::Menu) {
+        const MAX_RECENT_ITEMS: usize = 10;
+
+        for child in menu_recent.get_children() {
+            menu_recent.remove(&child);
+        }
+
+        let mut items: Vec<_> = gtk::RecentManager::get_default()
+            .get_items()
+            .into_iter()
+            .filter(|info| info.has_application(RECENT_APPLICATION_NAME) && info.exists())
+            .collect();
+        items.sort_by_key(|info| std::cmp::Reverse(info.get_modified()));
+
+        let paths: Vec<_> = items
+            .iter()
+            .filter_map(|info| info.get_uri())
+            .filter_map(|uri| glib::filename_from_uri(&uri).ok())
+            .map(|(path, _)| path)
+            .take(MAX_RECENT_ITEMS)
+            .collect();
+
+        for path in paths {
+            let item = MenuItem::with_label(&path.display().to_string());
+            item.connect_activate({
+                let ui = self.clone();
+                move |_| ui.add_files(vec![path.clone()])
+            });
+            menu_recent.append(&item);
+        }
+
+        menu_recent.show_all();
+        menuitem_recent.set_sensitive(!menu_recent.get_children().is_empty());
+    }
+
+    /// Show the ACDSee data of `file` and its XMP data before and after conversion in a dialog
+    fn show_metadata(&self, file: Arc<MetadataFile>) {
+        const RESPONSE_OPEN: gtk::ResponseType = gtk::ResponseType::Other(1);
+
+        let dialog = gtk::Dialog::with_buttons(
+            Some(&format!("Métadonnées de {}", file.path().display())),
+            Some(&self.window),
+            gtk::DialogFlags::DESTROY_WITH_PARENT,
+            &[
+                ("_Ouvrir dans le gestionnaire de fichiers", RESPONSE_OPEN),
+                ("_Fermer", gtk::ResponseType::Close),
+            ],
+        );
+        dialog.set_default_size(640, 480);
+
+        let notebook = gtk::Notebook::new();
+        let add_page = |title: &str| {
+            let buffer = gtk::TextBuffer::new(None::<&gtk::TextTagTable>);
+            let view = gtk::TextView::with_buffer(&buffer);
+            view.set_editable(false);
+            view.set_monospace(true);
+
+            let scrolled =
+                gtk::ScrolledWindow::new(None::<&gtk::Adjustment>, None::<&gtk::Adjustment>);
+            scrolled.add(&view);
+            notebook.append_page(&scrolled, Some(&gtk::Label::with_mnemonic(title)));
+
+            buffer
+        };
+
+        let buffer_acdsee = add_page("Données _ACDSee");
+        let buffer_original = add_page("XMP d'_origine");
+        let buffer_converted = add_page("XMP _converti");
+
+        dialog
+            .get_content_area()
+            .pack_start(&notebook, true, true, 0);
+
+        dialog.connect_response({
+            let path = file.path().to_path_buf();
+
+            move |dialog, response| {
+                if response == RESPONSE_OPEN {
+                    show_in_file_manager(path.clone());
+                } else {
+                    dialog.close();
+                }
+            }
+        });
+        dialog.show_all();
+
+        glib::MainContext::default().spawn_local(async move {
+            let text = match file.read_acdsee_data().await {
+                Ok(Some(data)) => serde_json::to_string_pretty(&data)
+                    .unwrap_or_else(|error| format!("Erreur: {}", error)),
+                Ok(None) => "Aucune donnée XMP présente".to_owned(),
+                Err(error) => format!("Erreur de lecture: {}", error),
+            };
+            buffer_acdsee.set_text(&text);
+
+            let text = match file.preview_xmp().await {
+                Ok(xmp) if xmp.is_empty() => "Aucune donnée XMP présente".to_owned(),
+                Ok(xmp) => xmp,
+                Err(error) => format!("Erreur de lecture: {}", error),
+            };
+            buffer_original.set_text(&text);
+
+            let mapping = Config::load().field_mapping;
+            let text = match file.preview_converted_xmp(&mapping).await {
+                Ok(xmp) if xmp.is_empty() => "Aucune donnée XMP présente".to_owned(),
+                Ok(xmp) => xmp,
+                Err(error) => format!("Erreur de conversion: {}", error),
+            };
+            buffer_converted.set_text(&text);
+        });
+    }
+
+    /// Ask for a JSON file, and export the ACDSee data of the listed files to it
+    fn show_export(&self) {
+        let filechooser = FileChooserNative::new(
+            Some("Exporter les métadonnées"),
+            Some(&self.window),
+            gtk::FileChooserAction::Save,
+            Some("_Exporter"),
+            Some("_Annuler"),
+        );
+        filechooser.set_do_overwrite_confirmation(true);
+        filechooser.set_current_name("metadata.json");
+
+        if filechooser.run() == gtk::ResponseType::Accept {
+            if let Some(path) = filechooser.get_filename() {
+                send_request(&self.service, Request::Export(path));
             }
         }
     }
 
+    /// Edit the user configuration in a dialog, and send it to the service when accepted
+    fn show_preferences(&self) {
+        let config = Config::load();
+
+        let dialog = gtk::Dialog::with_buttons(
+            Some("Préférences"),
+            Some(&self.window),
+            gtk::DialogFlags::DESTROY_WITH_PARENT | gtk::DialogFlags::MODAL,
+            &[
+                ("_Annuler", gtk::ResponseType::Cancel),
+                ("_OK", gtk::ResponseType::Ok),
+            ],
+        );
+        dialog.set_default_response(gtk::ResponseType::Ok);
+
+        let grid = gtk::Grid::new();
+        grid.set_row_spacing(6);
+        grid.set_column_spacing(12);
+        grid.set_border_width(12);
+
+        let add_row = |top: i32, label: &str, widget: &gtk::Widget| {
+            let label = gtk::Label::with_mnemonic(label);
+            label.set_halign(gtk::Align::End);
+            label.set_mnemonic_widget(Some(widget));
+            grid.attach(&label, 0, top, 1, 1);
+
+            widget.set_hexpand(true);
+            grid.attach(widget, 1, top, 1, 1);
+        };
+
+        let combo_backup = gtk::ComboBoxText::new();
+        for mode in BackupMode::all() {
+            combo_backup.append_text(mode.label());
+        }
+        combo_backup.set_active(Some(config.backup_mode.index()));
+        add_row(0, "_Sauvegardes :", combo_backup.upcast_ref());
+
+        let entry_separator = gtk::Entry::new();
+        entry_separator.set_max_length(1);
+        entry_separator.set_text(&config.field_mapping.tag_separator.to_string());
+        entry_separator.set_tooltip_text(Some(
+            "Séparateur des niveaux de catégories dans lr:hierarchicalSubject",
+        ));
+        add_row(
+            1,
+            "Séparateur de _catégories :",
+            entry_separator.upcast_ref(),
+        );
+
+        let check_strip_history = gtk::CheckButton::with_mnemonic(
+            "Supprimer l'_historique (xmpMM:History) lors de la conversion",
+        );
+        check_strip_history.set_active(config.field_mapping.strip_history_before_convert);
+        check_strip_history.set_tooltip_text(Some(
+            "Libère de la place pour les fichiers modifiés de nombreuses fois",
+        ));
+        grid.attach(&check_strip_history, 1, 2, 1, 1);
+
+        let check_strip_acdsee =
+            gtk::CheckButton::with_mnemonic("Supprimer les champs _ACDSee après la conversion");
+        check_strip_acdsee.set_active(config.field_mapping.strip_acdsee_after_convert);
+        grid.attach(&check_strip_acdsee, 1, 3, 1, 1);
+
+        let entry_log_file = gtk::Entry::new();
+        if let Some(log_file) = &config.log_file {
+            entry_log_file.set_text(&log_file.display().to_string());
+        }
+        entry_log_file.set_placeholder_text(Some("Aucun"));
+        entry_log_file.set_tooltip_text(Some("Pris en compte au prochain démarrage"));
+        add_row(4, "Fichier de _journal :", entry_log_file.upcast_ref());
+
+        dialog.get_content_area().pack_start(&grid, true, true, 0);
+        dialog.show_all();
+
+        let response = dialog.run();
+        dialog.close();
+
+        if response != gtk::ResponseType::Ok {
+            return;
+        }
+
+        let mut config = config;
+
+        if let Some(backup_mode) = combo_backup
+            .get_active()
+            .and_then(|index| index.try_into().ok())
+        {
+            config.backup_mode = backup_mode;
+
+            let combobox_backups: ComboBox = self.builder.get_object("combobox_backups").unwrap();
+            combobox_backups.set_active(Some(config.backup_mode.index()));
+        }
+
+        if let Some(separator) = entry_separator.get_text().chars().next() {
+            config.field_mapping.tag_separator = separator;
+        }
+
+        config.field_mapping.strip_history_before_convert = check_strip_history.get_active();
+        config.field_mapping.strip_acdsee_after_convert = check_strip_acdsee.get_active();
+
+        let log_file = entry_log_file.get_text();
+        config.log_file = if log_file.is_empty() {
+            None
+        } else {
+            Some(PathBuf::from(log_file.as_str()))
+        };
+
+        if let Err(error) = config.save() {
+            warn!(
+                ui = true,
+                "Impossible d'enregistrer les préférences : {}", error
+            );
+        }
+
+        send_request(&self.service, Request::UpdateConfig(config));
+    }
+
     fn handle_message(
         &self,
         item: Message,
         statusbar: &Statusbar,
-        file_list: &gio::ListStore,
+        file_list: &gtk::ListStore,
         progress: &ProgressBar,
         controls: &impl gtk::WidgetExt,
+        cancel: &impl gtk::WidgetExt,
     ) {
         match item {
             Message::Status(message) => {
@@ -66,6 +418,8 @@ impl Ui {
                 statusbar.push(context, &message);
             }
             Message::AddPathsComplete(results) => {
+                self.stop_pulse(progress);
+
                 let ok_count = results.iter().filter(|res| res.is_ok()).count();
                 let total = results.len();
                 let err_count = total - ok_count;
@@ -106,38 +460,118 @@ impl Ui {
                 for event in events {
                     match event {
                         Event::Added { start, files } => {
-                            file_list.splice(
-                                start as _,
-                                0,
-                                &files
-                                    .into_iter()
-                                    .map(RowData::new)
-                                    .map(|row_data| row_data.upcast::<glib::Object>())
-                                    .collect::<Vec<_>>(),
-                            );
+                            for (i, file) in files.into_iter().enumerate() {
+                                let iter = file_list.insert((start + i) as _);
+                                set_row(file_list, &iter, file);
+                            }
+                        }
+                        Event::Removed { index } => {
+                            if let Some(iter) = file_list.iter_nth_child(None, index as _) {
+                                file_list.remove(&iter);
+                            }
                         }
                         Event::Changed { start, files } => {
-                            file_list.splice(
-                                start as _,
-                                files.len() as _,
-                                &files
-                                    .into_iter()
-                                    .map(RowData::new)
-                                    .map(|row_data| row_data.upcast::<glib::Object>())
-                                    .collect::<Vec<_>>(),
-                            );
+                            for (i, file) in files.into_iter().enumerate() {
+                                if let Some(iter) = file_list.iter_nth_child(None, (start + i) as _)
+                                {
+                                    set_row(file_list, &iter, file);
+                                }
+                            }
                         }
                     }
                 }
             }
-            Message::ProgressUpdate { current, total } => {
-                if current == total {
-                    progress.set_fraction(0.);
-                    controls.set_sensitive(true);
-                } else {
-                    progress.set_fraction(current as f64 / total as f64);
-                    controls.set_sensitive(false);
+            Message::ProgressUpdate {
+                current,
+                total,
+                kind,
+                pending,
+                current_file,
+            } => {
+                if total > 0 {
+                    self.stop_pulse(progress);
+                }
+
+                progress.set_fraction(current as f64 / total as f64);
+
+                let mut text = format!(
+                    "{}... ({}/{}, {} en attente)",
+                    kind.label(),
+                    current,
+                    total,
+                    pending
+                );
+                if let Some(filename) = current_file {
+                    text.push_str(" : ");
+                    text.push_str(&filename);
+                }
+
+                progress.set_text(Some(&text));
+                progress.set_show_text(true);
+                controls.set_sensitive(false);
+                cancel.set_sensitive(true);
+            }
+            Message::BatchComplete {
+                kind,
+                applied,
+                already_done,
+                errors,
+            } => {
+                progress.set_fraction(0.);
+                progress.set_show_text(false);
+                controls.set_sensitive(true);
+                cancel.set_sensitive(false);
+
+                if kind == ProgressKind::Apply {
+                    let context = statusbar.get_context_id("description");
+                    statusbar.push(
+                        context,
+                        &format!(
+                            "Traitement terminé : {} converti(s), {} ignoré(s), {} erreur(s)",
+                            applied, already_done, errors
+                        ),
+                    );
+                }
+            }
+            Message::Cancelled => {
+                progress.set_fraction(0.);
+                progress.set_show_text(false);
+                controls.set_sensitive(true);
+                cancel.set_sensitive(false);
+
+                let context = statusbar.get_context_id("description");
+                statusbar.push(context, "Traitement annulé");
+            }
+            Message::FatalError(message) => {
+                self.stop_pulse(progress);
+
+                error!(%message, "backend service failed");
+
+                let dialog = gtk::MessageDialog::new(
+                    Some(&self.window),
+                    gtk::DialogFlags::DESTROY_WITH_PARENT | gtk::DialogFlags::MODAL,
+                    gtk::MessageType::Error,
+                    gtk::ButtonsType::Close,
+                    &format!("Erreur fatale du service de traitement :\n{}", message),
+                );
+
+                dialog.set_property_secondary_text(Some(
+                    "Le redémarrage du service vide la liste des fichiers.",
+                ));
+                dialog.add_button("_Redémarrer le service", gtk::ResponseType::Accept);
+
+                let response = dialog.run();
+                dialog.close();
+
+                if response == gtk::ResponseType::Accept {
+                    self.restart_dead_service(file_list);
                 }
+
+                // Re-enable the window
+                progress.set_fraction(0.);
+                controls.set_sensitive(true);
+                cancel.set_sensitive(false);
+                self.window.set_sensitive(true);
             }
         }
     }
@@ -159,122 +593,372 @@ impl Ui {
             }
         }
 
-        let menu_open: MenuItem = builder.get_object("menu_open").unwrap();
-        menu_open.connect_activate(
+        // Window actions, bound to the menu items and to the keyboard shortcuts
+        let action_open = gio::SimpleAction::new("open", None);
+        action_open.connect_activate(
             self.clone()
                 .open_callback(builder.get_object("filechooser").unwrap()),
         );
+        window.add_action(&action_open);
 
-        let menu_open_folder: MenuItem = builder.get_object("menu_open_folder").unwrap();
-        menu_open_folder.connect_activate(
+        let action_open_folder = gio::SimpleAction::new("open-folder", None);
+        action_open_folder.connect_activate(
             self.clone()
                 .open_callback(builder.get_object("filechooser_folder").unwrap()),
         );
+        window.add_action(&action_open_folder);
+
+        let action_export = gio::SimpleAction::new("export", None);
+        action_export.connect_activate({
+            let ui = self.clone();
+            move |_, _| ui.show_export()
+        });
+        window.add_action(&action_export);
 
-        let menu_quit: MenuItem = builder.get_object("menu_quit").unwrap();
-        menu_quit.connect_activate(clone!(@weak window => move |_| {
+        let action_quit = gio::SimpleAction::new("quit", None);
+        action_quit.connect_activate(clone!(@weak window => move |_, _| {
             window.close();
         }));
+        window.add_action(&action_quit);
 
-        // Create the list model
-        let list = gio::ListStore::new(RowData::static_type());
-        let listbox: ListBox = builder.get_object("listbox").unwrap();
-        listbox.bind_model(Some(&list), move |item| {
-            let box_ = gtk::ListBoxRow::new();
-            box_.set_margin_start(12);
-            box_.set_margin_end(12);
-
-            let item = item.downcast_ref::<RowData>().unwrap();
+        let action_preferences = gio::SimpleAction::new("preferences", None);
+        action_preferences.connect_activate({
+            let ui = self.clone();
+            move |_, _| ui.show_preferences()
+        });
+        window.add_action(&action_preferences);
 
-            let hbox = gtk::Box::new(gtk::Orientation::Horizontal, 12);
+        // Accept files and folders dropped on the window, which is highlighted during drag-over
+        window.drag_dest_set(
+            gtk::DestDefaults::ALL,
+            &[gtk::TargetEntry::new(
+                "text/uri-list",
+                gtk::TargetFlags::OTHER_APP,
+                0,
+            )],
+            gdk::DragAction::COPY,
+        );
+        window.connect_drag_data_received({
+            let ui = self.clone();
 
-            let label_path = gtk::Label::new(None);
-            item.bind_property("path", &label_path, "label")
-                .flags(glib::BindingFlags::DEFAULT | glib::BindingFlags::SYNC_CREATE)
-                .build();
-            label_path.set_halign(gtk::Align::Start);
-            hbox.pack_start(&label_path, true, true, 0);
+            move |_, _, _, _, data, _, _| {
+                let paths = data
+                    .get_uris()
+                    .iter()
+                    .filter_map(|uri| glib::filename_from_uri(uri).ok())
+                    .map(|(path, _)| path)
+                    .collect();
 
-            let label_state = gtk::Label::new(None);
-            item.bind_property("state", &label_state, "label")
-                .flags(glib::BindingFlags::DEFAULT | glib::BindingFlags::SYNC_CREATE)
-                .build();
-            hbox.pack_start(&label_state, false, false, 0);
+                ui.add_files(paths);
+            }
+        });
 
-            box_.add(&hbox);
+        let menuitem_recent: MenuItem = builder.get_object("menuitem_recent").unwrap();
+        let menu_recent: gtk::Menu = builder.get_object("menu_recent").unwrap();
+        self.update_recent_menu(&menuitem_recent, &menu_recent);
+        gtk::RecentManager::get_default().connect_changed({
+            let ui = self.clone();
+            move |_| ui.update_recent_menu(&menuitem_recent, &menu_recent)
+        });
 
-            box_.show_all();
+        // File list, filtered by the search bar and sorted by the column headers
+        let list: gtk::ListStore = builder.get_object("liststore_files").unwrap();
+        let filter: gtk::TreeModelFilter = builder.get_object("treemodelfilter_files").unwrap();
+        let sort: gtk::TreeModelSort = builder.get_object("treemodelsort_files").unwrap();
+        let treeview: TreeView = builder.get_object("treeview_files").unwrap();
 
-            box_.upcast::<gtk::Widget>()
+        // Keep the number of files up to date
+        let label_file_count: gtk::Label = builder.get_object("label_file_count").unwrap();
+        let update_file_count = move |list: &gtk::ListStore| {
+            label_file_count.set_text(&format!("{} fichier(s)", list.iter_n_children(None)));
+        };
+        list.connect_row_inserted({
+            let update_file_count = update_file_count.clone();
+            move |list, _, _| update_file_count(list)
         });
+        list.connect_row_deleted(move |list, _| update_file_count(list));
 
-        listbox.set_activate_on_single_click(false);
-        listbox.connect_row_activated(clone!(@weak list => move |_, row| {
-            let file = list.get_object(row.get_index() as _).unwrap();
-            let file = file.downcast_ref::<RowData>().unwrap();
-            let path = file.path();
-
-            async_std::task::spawn(async move {
-                if let Some(p) = async_std::fs::canonicalize(path).await.ok() {
-                    tracing::info!(path = %p.display(), "opening");
-
-                    if cfg!(target_os = "linux") {
-                        std::process::Command::new("dbus-send").args(&[
-                            OsString::from("--session"),
-                            OsString::from("--print-reply"),
-                            OsString::from("--dest=org.freedesktop.FileManager1"),
-                            OsString::from("/org/freedesktop/FileManager1"),
-                            OsString::from("org.freedesktop.FileManager1.ShowItems"),
-                            {
-                                let mut s = OsString::from("array:string:file:");
-                                s.push(p);
-                                s
-                            },
-                            OsString::from("string:"),
-                        ]).spawn().ok();
-                    } else if cfg!(target_os = "windows") {
-                        if let Some(explorer) = std::env::var_os("WINDIR").map(|windir| {
-                            let mut path = std::path::PathBuf::from(windir);
-                            path.push("explorer.exe");
-                            path
-                        }) {
-                            std::process::Command::new(explorer).args(&[
-                                {
-                                    let mut s = OsString::from("/select,");
-                                    s.push(&p);
-                                    s
-                                }
-                            ]).spawn().ok();
-                        } else {
-                            tracing::warn!("windows folder not found");
-                            return;
-                        }
-                    } else {
-                        tracing::warn!("not supported");
-                        return;
+        for (column, sort_column) in treeview.get_columns().into_iter().zip(&[
+            COLUMN_FILENAME,
+            COLUMN_DIRECTORY,
+            COLUMN_STATE_KIND,
+            COLUMN_SIZE,
+            COLUMN_MODIFIED,
+        ]) {
+            let sort_column = *sort_column;
+
+            column.set_clickable(true);
+            column.connect_clicked(clone!(@weak sort, @weak treeview => move |column| {
+                // Clicking the current sort column again reverses the order
+                let order = match sort.get_sort_column_id() {
+                    Some((gtk::SortColumn::Index(current), gtk::SortType::Ascending))
+                        if current == sort_column =>
+                    {
+                        gtk::SortType::Descending
                     }
+                    _ => gtk::SortType::Ascending,
+                };
+
+                for other in treeview.get_columns() {
+                    other.set_sort_indicator(false);
                 }
-            });
+
+                column.set_sort_indicator(true);
+                column.set_sort_order(order);
+                sort.set_sort_column_id(gtk::SortColumn::Index(sort_column), order);
+            }));
+        }
+
+        // Filter the list by path
+        let searchbar: gtk::SearchBar = builder.get_object("searchbar").unwrap();
+        let searchentry: gtk::SearchEntry = builder.get_object("searchentry").unwrap();
+        searchbar.connect_entry(&searchentry);
+
+        let togglebutton_search: ToggleButton = builder.get_object("togglebutton_search").unwrap();
+        togglebutton_search
+            .bind_property("active", &searchbar, "search-mode-enabled")
+            .flags(glib::BindingFlags::BIDIRECTIONAL | glib::BindingFlags::SYNC_CREATE)
+            .build();
+
+        filter.set_visible_func(
+            clone!(@weak searchentry => @default-return true, move |model, iter| {
+                let needle = searchentry.get_text().to_lowercase();
+                if needle.is_empty() {
+                    return true;
+                }
+
+                file_at(model, iter)
+                    .map(|file| file.path().to_string_lossy().to_lowercase().contains(&needle))
+                    .unwrap_or(true)
+            }),
+        );
+        searchentry.connect_search_changed(clone!(@weak filter => move |_| {
+            filter.refilter();
         }));
 
+        treeview.connect_row_activated({
+            let ui = self.clone();
+
+            move |treeview, path, _| {
+                if let Some(file) = treeview
+                    .get_model()
+                    .and_then(|model| model.get_iter(path).and_then(|iter| file_at(&model, &iter)))
+                {
+                    ui.show_metadata(file);
+                }
+            }
+        });
+
+        // Right-click menu for the selected rows
+        treeview.connect_button_press_event({
+            let ui = self.clone();
+
+            move |treeview, event| {
+                if event.get_event_type() != gdk::EventType::ButtonPress || event.get_button() != 3
+                {
+                    return Inhibit(false);
+                }
+
+                let (x, y) = event.get_position();
+                let path = match treeview.get_path_at_pos(x as i32, y as i32) {
+                    Some((Some(path), _, _, _)) => path,
+                    _ => return Inhibit(false),
+                };
+
+                // Right-clicking outside of the selection selects the clicked row
+                let selection = treeview.get_selection();
+                if !selection.path_is_selected(&path) {
+                    selection.unselect_all();
+                    selection.select_path(&path);
+                }
+
+                let file = match treeview.get_model().and_then(|model| {
+                    model
+                        .get_iter(&path)
+                        .and_then(|iter| file_at(&model, &iter))
+                }) {
+                    Some(file) => file,
+                    None => return Inhibit(false),
+                };
+
+                let selected_paths: Vec<_> = selected_files(&selection)
+                    .iter()
+                    .map(|file| file.path().to_path_buf())
+                    .collect();
+
+                let menu = gtk::Menu::new();
+
+                let item_open = MenuItem::with_mnemonic("_Ouvrir dans le gestionnaire de fichiers");
+                item_open.connect_activate({
+                    let path = file.path().to_path_buf();
+                    move |_| show_in_file_manager(path.clone())
+                });
+                menu.append(&item_open);
+
+                let item_metadata = MenuItem::with_mnemonic("Afficher les _métadonnées");
+                item_metadata.connect_activate({
+                    let ui = ui.clone();
+                    let file = file.clone();
+                    move |_| ui.show_metadata(file.clone())
+                });
+                menu.append(&item_metadata);
+
+                let item_copy = MenuItem::with_mnemonic("_Copier le chemin");
+                item_copy.connect_activate({
+                    let path = file.path().to_path_buf();
+                    move |_| {
+                        gtk::Clipboard::get(&gdk::SELECTION_CLIPBOARD)
+                            .set_text(&path.to_string_lossy());
+                    }
+                });
+                menu.append(&item_copy);
+
+                menu.append(&gtk::SeparatorMenuItem::new());
+
+                let item_remove = MenuItem::with_mnemonic("_Retirer de la liste");
+                item_remove.connect_activate({
+                    let svc = ui.service.clone();
+                    move |_| {
+                        send_request(&svc, Request::Remove(selected_paths.clone()));
+                    }
+                });
+                menu.append(&item_remove);
+
+                menu.set_attach_widget(Some(treeview));
+                menu.show_all();
+                menu.popup_at_pointer(Some(&**event));
+
+                Inhibit(true)
+            }
+        });
+
         let button_apply: Button = builder.get_object("button_apply").unwrap();
         let combobox_backups: ComboBox = builder.get_object("combobox_backups").unwrap();
+        let liststore_backupmodes: gtk::ListStore =
+            builder.get_object("liststore_backupmodes").unwrap();
+        for mode in BackupMode::all() {
+            liststore_backupmodes.insert_with_values(None, &[0], &[&mode.label()]);
+        }
+        combobox_backups.set_active(Some(Config::load().backup_mode.index()));
+
+        let send_apply = {
+            let svc = self.service.clone();
+
+            move |filter: ApplyFilter, recheck: bool| match combobox_backups
+                .get_active()
+                .unwrap_or(0)
+                .try_into()
+            {
+                Ok(backup_mode) => {
+                    send_request(
+                        &svc,
+                        Request::Apply {
+                            backup_mode,
+                            filter,
+                            recheck,
+                        },
+                    );
+                }
+                Err(error) => {
+                    tracing::warn!(%error, "invalid backup mode selection");
+                }
+            }
+        };
+
         button_apply.connect_clicked({
+            let send_apply = send_apply.clone();
+            move |_| send_apply(ApplyFilter::All, false)
+        });
+
+        let button_apply_all: Button = builder.get_object("button_apply_all").unwrap();
+        button_apply_all.connect_clicked({
+            let send_apply = send_apply.clone();
+            move |_| send_apply(ApplyFilter::All, true)
+        });
+
+        let button_apply_selected: Button = builder.get_object("button_apply_selected").unwrap();
+        button_apply_selected.connect_clicked(clone!(@weak treeview => move |_| {
+            let paths = selected_files(&treeview.get_selection())
+                .iter()
+                .map(|file| file.path().to_path_buf())
+                .collect();
+
+            send_apply(ApplyFilter::Selected(paths), false);
+        }));
+
+        treeview.get_selection().connect_changed(
+            clone!(@weak button_apply_selected => move |selection| {
+                button_apply_selected.set_sensitive(selection.count_selected_rows() > 0);
+            }),
+        );
+
+        let togglebutton_watch: ToggleButton = builder.get_object("togglebutton_watch").unwrap();
+        let filechooser_watch: FileChooserNative =
+            builder.get_object("filechooser_folder").unwrap();
+        togglebutton_watch.connect_toggled({
             let svc = self.service.clone();
 
-            move |_| {
-                if let Some(service) = &*svc.borrow() {
-                    service.send_request(Request::Apply(
-                        combobox_backups
-                            .get_active()
-                            .unwrap_or(0)
-                            .try_into()
-                            .unwrap(),
-                    ));
+            move |button| {
+                // The service is only locked while sending: the file chooser runs a nested main
+                // loop, and unchecking the button runs this handler again
+                if button.get_active() {
+                    if filechooser_watch.run() == gtk::ResponseType::Accept {
+                        for dir in filechooser_watch.get_filenames() {
+                            send_request(&svc, Request::WatchDir(dir));
+                        }
+                    } else {
+                        // No folder to watch
+                        button.set_active(false);
+                    }
+                } else {
+                    send_request(&svc, Request::Unwatch);
                 }
             }
         });
 
+        let button_cancel: Button = builder.get_object("button_cancel").unwrap();
+        button_cancel.connect_clicked({
+            let svc = self.service.clone();
+
+            move |_| {
+                send_request(&svc, Request::Cancel);
+            }
+        });
+
+        // Apply and cancel are only enabled when the matching buttons can be clicked
+        let box_controls: gtk::Box = builder.get_object("box_controls").unwrap();
+
+        let action_apply = gio::SimpleAction::new("apply", None);
+        action_apply.connect_activate(clone!(@weak button_apply => move |_, _| {
+            button_apply.clicked();
+        }));
+        box_controls
+            .bind_property("sensitive", &action_apply, "enabled")
+            .flags(glib::BindingFlags::SYNC_CREATE)
+            .build();
+        window.add_action(&action_apply);
+
+        let action_cancel = gio::SimpleAction::new("cancel", None);
+        action_cancel.connect_activate(clone!(@weak button_cancel => move |_, _| {
+            button_cancel.clicked();
+        }));
+        button_cancel
+            .bind_property("sensitive", &action_cancel, "enabled")
+            .flags(glib::BindingFlags::SYNC_CREATE)
+            .build();
+        window.add_action(&action_cancel);
+
+        // Check the backend service periodically, in case it stopped unexpectedly
+        glib::timeout_add_local(5000, {
+            let ui = self.clone();
+            let list = list.clone();
+
+            move || {
+                ui.check_service(&list);
+                glib::Continue(true)
+            }
+        });
+
         rx.attach(None, {
             let ui = self.clone();
             let statusbar: Statusbar = builder.get_object("statusbar").unwrap();
@@ -282,9 +966,216 @@ impl Ui {
             let box_: gtk::Box = builder.get_object("box_controls").unwrap();
 
             move |item| {
-                ui.handle_message(item, &statusbar, &list, &progress, &box_);
+                ui.handle_message(item, &statusbar, &list, &progress, &box_, &button_cancel);
                 glib::Continue(true)
             }
         });
     }
 }
+
+/// Send `request` to the service, returning `false` if it is not running
+///
+/// The service is only locked while sending, so this must not be called with the lock held.
+fn send_request(service: &SharedServiceHandle, request: Request) -> bool {
+    match &*service.lock().unwrap() {
+        Some(service) => match service.send_request(request) {
+            Ok(()) => true,
+            Err(error) => {
+                warn!(ui = true, "Impossible d'envoyer la requête : {}", error);
+                false
+            }
+        },
+        None => false,
+    }
+}
+
+/// Fill the columns of the row at `iter` from `file`
+fn set_row(store: &gtk::ListStore, iter: &gtk::TreeIter, file: Arc<MetadataFile>) {
+    let path = file.path();
+    let filename = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let directory = path
+        .parent()
+        .map(|dir| dir.display().to_string())
+        .unwrap_or_default();
+
+    let row_data = RowData::new(file.clone());
+    let size = row_data.size();
+    // The size is unknown until the file is checked
+    let size_text = if file.size().is_some() {
+        format_size(size)
+    } else {
+        String::new()
+    };
+    let modified = row_data.modified();
+    let modified_text = if modified > 0 {
+        chrono::Local
+            .timestamp(modified as i64, 0)
+            .format("%d/%m/%Y %H:%M")
+            .to_string()
+    } else {
+        String::new()
+    };
+
+    let state = file.state();
+
+    // Highlight failed and converted files
+    let foreground = match FileStateKind::from(state) {
+        FileStateKind::Complete => Some("#26a269"),
+        FileStateKind::IoError
+        | FileStateKind::ContainerError
+        | FileStateKind::XmpRewriteError
+        | FileStateKind::InvalidAcdseeData
+        | FileStateKind::RewriteError
+        | FileStateKind::ApplyError
+        | FileStateKind::BackupError => Some("#c01c28"),
+        FileStateKind::Init
+        | FileStateKind::NoXmpData
+        | FileStateKind::NoAcdData
+        | FileStateKind::AlreadyConverted
+        | FileStateKind::PartialAcdData
+        | FileStateKind::Ready => None,
+    };
+
+    // Some fields will be ignored when converting this file
+    let icon = if matches!(state, FileState::PartialAcdData(_, _)) {
+        Some("dialog-warning")
+    } else {
+        None
+    };
+
+    let tooltip = if let FileState::Complete(stats, _) = state {
+        Some(format!(
+            "Écrit en {} ms ({:.1} Ko/s)",
+            stats.write_duration.as_millis(),
+            stats.write_speed()
+        ))
+    } else if state.into_error().is_some() {
+        // The state column is truncated, long error messages are only readable in full here
+        Some(state.to_string())
+    } else {
+        None
+    };
+
+    store.set(
+        iter,
+        &[
+            COLUMN_FILE,
+            COLUMN_FILENAME,
+            COLUMN_DIRECTORY,
+            COLUMN_STATE,
+            COLUMN_STATE_KIND,
+            COLUMN_SIZE,
+            COLUMN_SIZE_TEXT,
+            COLUMN_ICON,
+            COLUMN_TOOLTIP,
+            COLUMN_MODIFIED,
+            COLUMN_MODIFIED_TEXT,
+            COLUMN_FOREGROUND,
+        ],
+        &[
+            &row_data,
+            &filename,
+            &directory,
+            &state.to_string(),
+            &(FileStateKind::from(state) as u32),
+            &size,
+            &size_text,
+            &icon,
+            &tooltip.map(|tooltip| glib::markup_escape_text(&tooltip).to_string()),
+            &modified,
+            &modified_text,
+            &foreground,
+        ],
+    );
+}
+
+/// Get the file stored in the row at `iter` of the file list `model`
+fn file_at(model: &impl IsA<gtk::TreeModel>, iter: &gtk::TreeIter) -> Option<Arc<MetadataFile>> {
+    model
+        .get_value(iter, COLUMN_FILE as _)
+        .get::<RowData>()
+        .ok()
+        .flatten()
+        .map(|row_data| row_data.inner())
+}
+
+/// Get the files of the rows currently selected in the file list
+fn selected_files(selection: &gtk::TreeSelection) -> Vec<Arc<MetadataFile>> {
+    let (paths, model) = selection.get_selected_rows();
+
+    paths
+        .iter()
+        .filter_map(|path| model.get_iter(path))
+        .filter_map(|iter| file_at(&model, &iter))
+        .collect()
+}
+
+/// Human-readable file size
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["o", "Ko", "Mo", "Go"];
+
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024. && unit < UNITS.len() - 1 {
+        value /= 1024.;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", size, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Show `path` in the file manager of the platform
+fn show_in_file_manager(path: PathBuf) {
+    async_std::task::spawn(async move {
+        if let Some(p) = async_std::fs::canonicalize(path).await.ok() {
+            tracing::info!(path = %p.display(), "opening");
+
+            if cfg!(target_os = "linux") {
+                std::process::Command::new("dbus-send")
+                    .args(&[
+                        OsString::from("--session"),
+                        OsString::from("--print-reply"),
+                        OsString::from("--dest=org.freedesktop.FileManager1"),
+                        OsString::from("/org/freedesktop/FileManager1"),
+                        OsString::from("org.freedesktop.FileManager1.ShowItems"),
+                        {
+                            let mut s = OsString::from("array:string:file:");
+                            s.push(p);
+                            s
+                        },
+                        OsString::from("string:"),
+                    ])
+                    .spawn()
+                    .ok();
+            } else if cfg!(target_os = "windows") {
+                if let Some(explorer) = std::env::var_os("WINDIR").map(|windir| {
+                    let mut path = std::path::PathBuf::from(windir);
+                    path.push("explorer.exe");
+                    path
+                }) {
+                    std::process::Command::new(explorer)
+                        .args(&[{
+                            let mut s = OsString::from("/select,");
+                            s.push(&p);
+                            s
+                        }])
+                        .spawn()
+                        .ok();
+                } else {
+                    tracing::warn!("windows folder not found");
+                    return;
+                }
+            } else {
+                tracing::warn!("not supported");
+                return;
+            }
+        }
+    });
+}
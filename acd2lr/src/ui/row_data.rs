@@ -1,5 +1,5 @@
-use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::UNIX_EPOCH;
 
 use glib::subclass;
 use glib::subclass::prelude::*;
@@ -11,13 +11,34 @@ use crate::svc::MetadataFile;
 
 #[derive(Clone, GBoxed)]
 #[gboxed(type_name = "ArcFile")]
-struct ArcFile(Arc<MetadataFile>);
+struct ArcFile {
+    file: Arc<MetadataFile>,
+}
+
+impl ArcFile {
+    fn new(file: Arc<MetadataFile>) -> Self {
+        Self { file }
+    }
+
+    fn size(&self) -> u64 {
+        // The metadata is read by the service when checking the file, not on the UI thread
+        self.file.size().unwrap_or(0)
+    }
+
+    fn modified(&self) -> u64 {
+        self.file
+            .modified()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0)
+    }
+}
 
 impl std::ops::Deref for ArcFile {
     type Target = MetadataFile;
 
     fn deref(&self) -> &Self::Target {
-        self.0.as_ref()
+        self.file.as_ref()
     }
 }
 
@@ -33,7 +54,7 @@ mod imp {
     }
 
     // GObject property definitions for our two values
-    static PROPERTIES: [subclass::Property; 3] = [
+    static PROPERTIES: [subclass::Property; 5] = [
         subclass::Property("path", |path| {
             glib::ParamSpec::string(
                 path,
@@ -52,6 +73,28 @@ mod imp {
                 glib::ParamFlags::READABLE,
             )
         }),
+        subclass::Property("size", |size| {
+            glib::ParamSpec::uint64(
+                size,
+                "Size",
+                "Size of the target file at its last check, in bytes",
+                0,
+                u64::MAX,
+                0, // Default value
+                glib::ParamFlags::READABLE,
+            )
+        }),
+        subclass::Property("modified", |modified| {
+            glib::ParamSpec::uint64(
+                modified,
+                "Modified",
+                "Modification time of the target file, in seconds since the Unix epoch",
+                0,
+                u64::MAX,
+                0, // Default value
+                glib::ParamFlags::READABLE,
+            )
+        }),
         subclass::Property("inner", |inner| {
             glib::ParamSpec::boxed(
                 inner,
@@ -121,6 +164,8 @@ mod imp {
                         Ok(inner.path().display().to_string().to_value())
                     }
                     subclass::Property("state", ..) => Ok(inner.state().to_string().to_value()),
+                    subclass::Property("size", ..) => Ok(inner.size().to_value()),
+                    subclass::Property("modified", ..) => Ok(inner.modified().to_value()),
                     _ => Err(()),
                 }
             } else {
@@ -144,7 +189,7 @@ glib_wrapper! {
 // initial values for our two properties and then returns the new instance
 impl RowData {
     pub fn new(inner: Arc<MetadataFile>) -> RowData {
-        glib::Object::new(Self::static_type(), &[("inner", &ArcFile(inner))])
+        glib::Object::new(Self::static_type(), &[("inner", &ArcFile::new(inner))])
             .expect("Failed to create row data")
             .downcast()
             .expect("Created row data is of wrong type")
@@ -156,11 +201,17 @@ impl RowData {
             .unwrap()
             .get_some::<&ArcFile>()
             .unwrap()
-            .0
+            .file
             .clone()
     }
 
-    pub fn path(&self) -> PathBuf {
-        self.inner().path().to_path_buf()
+    /// Size of the file at its last check, in bytes
+    pub fn size(&self) -> u64 {
+        self.get_property("size").unwrap().get_some().unwrap()
+    }
+
+    /// Modification time of the file at its last check, in seconds since the Unix epoch
+    pub fn modified(&self) -> u64 {
+        self.get_property("modified").unwrap().get_some().unwrap()
     }
 }